@@ -1,7 +1,9 @@
 #version 150
 
+in vec4 v_color;
+
 out lowp vec4 color;
 
 void main() {
-  color = vec4(0.0, 0.0, 0.0, 0.5);
+  color = v_color;
 }
@@ -19,7 +19,7 @@ vec2 sprite_texture(vec2 pos) {
 }
 
 vec2 sprite_offset(vec2 size) {
-  return (vec2(48, 48) - size) / vec2(2, 2);
+  return (vec2(tile_size) - size) / vec2(2, 2);
 }
 
 void main() {
@@ -1,11 +1,26 @@
 #version 150
 
 in highp vec2 v_TexCoords;
+flat in ivec2 v_cell;
 
 uniform lowp sampler2D tex;
 
+// How much to wash out each cell on screen, in the red channel, starting
+// with the cell at fog_origin.
+uniform lowp sampler2D fog;
+uniform ivec2 fog_origin;
+
 out lowp vec4 color;
 
 void main() {
-  color = texture(tex, v_TexCoords);
+  vec4 tex_color = texture(tex, v_TexCoords);
+
+  ivec2 fog_cell = v_cell - fog_origin;
+  float washed = 0.0;
+  if (all(greaterThanEqual(fog_cell, ivec2(0))) && all(lessThan(fog_cell, textureSize(fog, 0)))) {
+    washed = texelFetch(fog, fog_cell, 0).r;
+  }
+
+  float luma = dot(tex_color.rgb, vec3(0.299, 0.587, 0.114));
+  color = vec4(mix(tex_color.rgb, vec3(luma), washed), tex_color.a);
 }
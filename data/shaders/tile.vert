@@ -3,16 +3,15 @@
 in uvec2 position;
 
 in vec2 tex_offset;
+in vec2 tex_ratio;
 in uvec2 map_coord;
 in int quadrant;
-in int autotile;
-in int autotile_index;
 
 uniform uvec2 tile_size;
 uniform mat4 matrix;
-uniform vec2 tex_ratio;
 
 out highp vec2 v_TexCoords;
+flat out ivec2 v_cell;
 
 vec2 quadrant_offset(int quadrant) {
   int qx = (quadrant % 2);
@@ -20,28 +19,26 @@ vec2 quadrant_offset(int quadrant) {
   return vec2(qx, qy);
 }
 
-vec2 autotile_offset(int index) {
-  float ax = (index % 4);
-  float ay = (index / 4);
-  return vec2(ax, ay);
-}
-
-vec2 normal_tile(vec2 pos, vec2 qoffset) {
+// tex_offset already points at the piece of the autotile picked for this
+// quadrant.
+vec2 quadrant_tile(vec2 pos) {
   float u = pos.x * tex_ratio.x + tex_offset.x;
   float v = 1.0 - (pos.y * tex_ratio.y + tex_offset.y);
   return vec2(u, v);
 }
 
-vec2 autotile_tile(vec2 pos, vec2 qoffset) {
-  return normal_tile(pos + autotile_offset(autotile_index), qoffset);
-}
-
 void main() {
+  v_cell = ivec2(map_coord);
+
+  // Blank slots have no quadrant. Putting every vertex at the same point
+  // leaves nothing to draw.
+  if (quadrant < 0) {
+    gl_Position = vec4(0.0, 0.0, 0.0, 1.0);
+    v_TexCoords = vec2(0.0, 0.0);
+    return;
+  }
+
   vec2 qoffset = quadrant_offset(quadrant);
   gl_Position = matrix * vec4(map_coord * tile_size * vec2(2.0, 2.0) + position * tile_size + qoffset * tile_size, 0.0, 2.0);
-  if (autotile > 0) {
-    v_TexCoords = autotile_tile(position, qoffset);
-  } else {
-    v_TexCoords = normal_tile(position, qoffset);
-  }
+  v_TexCoords = quadrant_tile(position);
 }
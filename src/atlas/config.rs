@@ -1,23 +1,46 @@
 use std::collections::HashMap;
 use std::fs::File;
 use std::path::{Path, PathBuf};
-use std::io::{Read, Write};
+use std::io::{self, Read, Write};
 
 use bincode;
 use crypto::digest::Digest;
 use crypto::sha3::Sha3;
 use glium::backend::Facade;
-use glob;
-use image;
+use image::{self, DynamicImage, RgbaImage};
+use serde::de::Deserialize;
 use toml::Value;
 
 use atlas::*;
+use atlas::error::{AtlasError, AtlasResult};
+use autotile::AutotileLayout;
 use util;
+use util::header;
+use util::toml::TomlError;
+
+/// Written at the start of `cache.bin`, followed by `CACHE_VERSION`.
+const CACHE_MAGIC: &'static [u8] = b"ATLS";
+
+/// Bump this whenever `TileManagerConfig` or anything it contains changes
+/// shape, so old caches are rebuilt instead of being misread.
+const CACHE_VERSION: u32 = 6;
 
 #[derive(Serialize, Deserialize)]
 pub struct TileManagerConfig {
-    pub locations: HashMap<TileIndex, String>,
-    pub frames: HashMap<String, AtlasFrame>,
+    pub names: HashMap<String, TileIndex>,
+    pub tiles: HashMap<TileIndex, PackedTile>,
+
+    /// File names of the packed pages, in `texture_idx` order.
+    pub pages: Vec<String>,
+
+    /// Size in pixels of a board cell on screen.
+    pub grid_size: (u32, u32),
+
+    /// How the pages were packed, for other atlases drawn alongside them.
+    pub packing: PackingSettings,
+
+    /// Hash of the atlas config, the sprite sheets it lists and the packer
+    /// settings the pages were built from.
     pub file_hash: String,
 }
 
@@ -26,127 +49,384 @@ pub fn get_config_cache_path(config_name: &str) -> PathBuf {
     PathBuf::from(&cache_filepath_str)
 }
 
-pub fn load_tile_manager_config(config_name: &str) -> TileManagerConfig {
+pub fn load_tile_manager_config(config_name: &str) -> AtlasResult<TileManagerConfig> {
     let mut path = get_config_cache_path(config_name);
     path.push("cache.bin");
+    let path_str = path.display().to_string();
 
-    let mut file = File::open(path).unwrap();
+    let mut file = File::open(&path).map_err(|e| AtlasError::Io(path_str.clone(), e))?;
     let mut buf = Vec::new();
-    file.read_to_end(&mut buf).unwrap();
-    bincode::deserialize(buf.as_slice()).unwrap()
+    file.read_to_end(&mut buf).map_err(|e| AtlasError::Io(path_str.clone(), e))?;
+    decode_cache(&buf).map_err(|msg| AtlasError::Cache(path_str, msg))
 }
 
-pub fn write_tile_manager_config(config: &TileManagerConfig, config_name: &str) {
+pub fn write_tile_manager_config(config: &TileManagerConfig, config_name: &str) -> AtlasResult<()> {
     let mut path = get_config_cache_path(config_name);
     path.push("cache.bin");
+    let path_str = path.display().to_string();
+
+    let data = encode_cache(config).map_err(|msg| AtlasError::Cache(path_str.clone(), msg))?;
+    let mut file = File::create(&path).map_err(|e| AtlasError::Io(path_str.clone(), e))?;
+    file.write_all(data.as_slice()).map_err(|e| AtlasError::Io(path_str, e))
+}
+
+fn encode_cache(config: &TileManagerConfig) -> Result<Vec<u8>, String> {
+    let mut data = header::write_header(CACHE_MAGIC, CACHE_VERSION);
+
+    let body = bincode::serialize(config, bincode::Infinite).map_err(|e| e.to_string())?;
+    data.extend_from_slice(&body);
+    Ok(data)
+}
+
+fn decode_cache(data: &[u8]) -> Result<TileManagerConfig, String> {
+    let body = match header::read_header(CACHE_MAGIC, data) {
+        Some((CACHE_VERSION, body)) => body,
+        Some(_)                     => return Err(format!("cache is from a different version (expected {})", CACHE_VERSION)),
+        None                        => return Err("not an atlas cache".to_string()),
+    };
 
-    let data = bincode::serialize(config, bincode::Infinite).unwrap();
-    let mut file = File::create(path).unwrap();
-    file.write(data.as_slice()).unwrap();
+    bincode::deserialize(body).map_err(|e| e.to_string())
 }
 
-fn hash_str(s: &str) -> String {
+/// Loads the cached atlas pages in the order `texture_idx` refers to them.
+fn load_pages<P, F>(folder: &Path, config: &TileManagerConfig, make_page: &F) -> AtlasResult<Vec<P>>
+    where F: Fn(DynamicImage) -> Result<P, String> {
+    let needed = config.tiles.values()
+        .flat_map(|tile| tile.cells.iter())
+        .map(|cell| cell.texture_idx + 1)
+        .max()
+        .unwrap_or(0);
+
+    if config.pages.len() < needed {
+        return Err(AtlasError::Cache(folder.display().to_string(),
+                                     format!("{} pages listed, but tiles refer to {}", config.pages.len(), needed)));
+    }
+
+    let mut textures = Vec::new();
+
+    for page in config.pages.iter() {
+        let path = folder.join(page);
+        let image = image::open(&path)
+            .map_err(|e| AtlasError::Image(path.display().to_string(), e))?;
+        textures.push(make_page(image).map_err(|msg| AtlasError::Texture(path.display().to_string(), msg))?);
+    }
+
+    Ok(textures)
+}
+
+/// Hashes everything the packed atlas is built from: the config text, the
+/// contents of every sprite sheet it lists and the packer settings.
+fn hash_sources(filename: &str, toml_str: &str) -> AtlasResult<String> {
     let mut hasher = Sha3::sha3_256();
-    hasher.input_str(s);
-    hasher.result_str()
+    hasher.input_str(toml_str);
+
+    let val = util::toml::toml_value_from_string(toml_str)
+        .map_err(|e| AtlasError::config(filename, None, e))?;
+    let maps: Vec<Value> = get_value(filename, None, &val, "maps")?;
+
+    for map in maps.iter() {
+        let map = read_map(filename, map)?;
+        let path = sheet_path(&map.name);
+
+        let mut bytes = Vec::new();
+        File::open(&path)
+            .and_then(|mut file| file.read_to_end(&mut bytes))
+            .map_err(|e| AtlasError::Io(path.clone(), e))?;
+        hasher.input(&bytes);
+    }
+
+    hasher.input_str(&packer_settings(&read_packing(filename, &val)?));
+    Ok(hasher.result_str())
 }
 
 impl TileManager {
-    pub fn from_config<F: Facade>(display: &F, filename: &str) -> Self {
-        let toml_str = util::toml::toml_string_from_file(filename);
+    pub fn from_config<F: Facade>(display: &F, filename: &str) -> AtlasResult<Self> {
+        TileManager::load(filename, |image| make_texture(display, image))
+    }
+}
+
+impl TileManager<RgbaImage> {
+    /// Loads the atlas pages into memory instead of onto the GPU, so the
+    /// tiles can be drawn without opening a window.
+    pub fn from_config_headless(filename: &str) -> AtlasResult<Self> {
+        TileManager::load(filename, |image| Ok(image.to_rgba()))
+    }
+}
 
-        let packed_folder = Path::new(filename).file_stem().unwrap().to_str().unwrap();
+impl<P> TileManager<P> {
+    fn load<F>(filename: &str, make_page: F) -> AtlasResult<Self>
+        where F: Fn(DynamicImage) -> Result<P, String> {
+        let toml_str = util::toml::toml_string_from_file(filename)
+            .map_err(|e| AtlasError::Io(filename.to_string(), e))?;
+
+        let packed_folder = match Path::new(filename).file_stem().and_then(|s| s.to_str()) {
+            Some(stem) => stem,
+            None       => return Err(AtlasError::Io(filename.to_string(),
+                                                    io::Error::new(io::ErrorKind::InvalidInput, "not a file name"))),
+        };
         let cache_filepath = get_config_cache_path(packed_folder);
+        let hash = hash_sources(filename, &toml_str)?;
 
         if !Path::exists(cache_filepath.as_path()) {
-            return TileManager::build_from_toml(display, packed_folder, &toml_str);
+            return TileManager::build_from_toml(filename, packed_folder, &toml_str, hash, make_page);
         }
 
-        // check if tile definitions were changed and only repack textures if
-        // so, saving startup time.
-
-        let cached_config = load_tile_manager_config(packed_folder);
+        // check if tile definitions or sprite sheets were changed and only
+        // repack textures if so, saving startup time.
 
-        let hash = hash_str(&toml_str);
+        let cached_config = match load_tile_manager_config(packed_folder) {
+            Ok(config) => config,
+            Err(e) => {
+                println!("Discarding tile atlas cache: {}", e);
+                return TileManager::build_from_toml(filename, packed_folder, &toml_str, hash, make_page);
+            },
+        };
 
         if cached_config.file_hash != hash {
-            return TileManager::build_from_toml(display, packed_folder, &toml_str);
+            return TileManager::build_from_toml(filename, packed_folder, &toml_str, hash, make_page);
         }
 
         println!("Using cached tile atlas config at {}/cache.bin", cache_filepath.display());
 
-        let mut textures = Vec::new();
+        let pages = load_pages(&cache_filepath, &cached_config, &make_page);
 
-        for entry in glob::glob(&format!("{}/*.png", cache_filepath.display())).unwrap() {
-            match entry {
-                Ok(path) => {
-                    let image = image::open(&path).unwrap();
-                    let texture = make_texture(display, image);
-                    textures.push(texture);
-                },
-                Err(..) => (),
-            }
+        match pages {
+            Ok(textures) => Ok(TileManager::new(cached_config, textures)),
+            Err(e) => {
+                println!("Discarding tile atlas cache: {}", e);
+                TileManager::build_from_toml(filename, packed_folder, &toml_str, hash, make_page)
+            },
         }
-
-        TileManager::new(cached_config, textures)
     }
 
-    fn build_from_toml<F: Facade>(display: &F, packed_folder: &str, toml_str: &str) -> Self {
+    fn build_from_toml<F>(filename: &str, packed_folder: &str, toml_str: &str, hash: String, make_page: F) -> AtlasResult<Self>
+        where F: Fn(DynamicImage) -> Result<P, String> {
         println!("Rebuilding tile atlas config \"{}\"", packed_folder);
 
-        let val = util::toml::toml_value_from_string(toml_str);
-
-        let mut idx = 0;
+        let val = util::toml::toml_value_from_string(toml_str)
+            .map_err(|e| AtlasError::config(filename, None, e))?;
 
-        let mut builder = TileManagerBuilder::new();
+        let mut builder = TileManagerBuilder::new(read_packing(filename, &val)?, read_grid_size(filename, &val)?);
 
-        let maps = match util::toml::expect_value_in_table(&val, "maps") {
-            Value::Array(array) => array,
-            _                   => panic!("Atlas config array wasn't an array."),
-        };
+        let maps: Vec<Value> = get_value(filename, None, &val, "maps")?;
 
         for map in maps.iter() {
-            let name: String = util::toml::expect_value_in_table(&map, "name");
-            let tile_size: [u32; 2] = util::toml::expect_value_in_table(&map, "tile_size");
-            let file_path = format!("data/texture/{}", name);
+            let map = read_map(filename, map)?;
+            let file_path = sheet_path(&map.name);
             println!("Load: {}", file_path);
-            builder.add_frame(&file_path, (tile_size[0], tile_size[1]));
+            builder.add_sheet(&file_path, map.tile_size)?;
+        }
+
+        let tiles: Vec<Value> = get_value(filename, None, &val, "tiles")?;
+
+        for (idx, tile) in tiles.iter().enumerate() {
+            let def = read_tile(filename, tile)?;
+            builder.add_tile(&sheet_path(&def.atlas), &def.name, idx, def.tile)?;
         }
 
-        let tiles = match util::toml::expect_value_in_table(&val, "tiles") {
-            Value::Array(array) => array,
-            _                   => panic!("Atlas config array wasn't an array."),
+        builder.file_hash = hash;
+
+        builder.build(packed_folder, make_page)
+    }
+}
+
+/// A sprite sheet listed under `maps` in an atlas config.
+pub struct MapDef {
+    pub name: String,
+    pub tile_size: (u32, u32),
+}
+
+/// A tile listed under `tiles` in an atlas config, before it is packed.
+pub struct TileDef {
+    pub name: String,
+    pub atlas: String,
+    pub tile: AtlasTile,
+}
+
+/// Gets the path of a sprite sheet from the name used in the atlas config.
+pub fn sheet_path(name: &str) -> String {
+    format!("data/texture/{}", name)
+}
+
+/// Reads the optional `[packing]` table, falling back to the defaults for
+/// anything left out.
+pub fn read_packing(path: &str, val: &Value) -> AtlasResult<PackingSettings> {
+    let mut packing = PackingSettings::default();
+
+    if let Some(table) = util::toml::get_value_in_table(val, "packing") {
+        if let Some(padding) = get_optional(path, None, table, "padding")? {
+            packing.padding = padding;
+        }
+        if let Some(extrude) = get_optional(path, None, table, "extrude")? {
+            packing.extrude = extrude;
+        }
+    }
+
+    Ok(packing)
+}
+
+/// Reads the board cell size from the optional `[grid]` table. Cells are
+/// drawn as four quadrants, so the size has to be even.
+pub fn read_grid_size(path: &str, val: &Value) -> AtlasResult<(u32, u32)> {
+    let table = match util::toml::get_value_in_table(val, "grid") {
+        Some(table) => table,
+        None        => return Ok(DEFAULT_GRID_SIZE),
+    };
+
+    match get_optional::<[u32; 2]>(path, None, table, "tile_size")? {
+        Some(size) => {
+            if size[0] == 0 || size[1] == 0 || size[0] % 2 != 0 || size[1] % 2 != 0 {
+                return Err(AtlasError::config(path, None, TomlError::InvalidValue("grid.tile_size".to_string())));
+            }
+            Ok((size[0], size[1]))
+        },
+        None => Ok(DEFAULT_GRID_SIZE),
+    }
+}
+
+pub fn read_map(path: &str, map: &Value) -> AtlasResult<MapDef> {
+    let name: String = get_value(path, None, map, "name")?;
+    let tile_size: [u32; 2] = get_value(path, None, map, "tile_size")?;
+
+    if tile_size[0] == 0 || tile_size[1] == 0 {
+        return Err(AtlasError::config(path, None, TomlError::InvalidValue("tile_size".to_string())));
+    }
+
+    Ok(MapDef {
+        name: name,
+        tile_size: (tile_size[0], tile_size[1]),
+    })
+}
+
+pub fn read_tile(path: &str, tile: &Value) -> AtlasResult<TileDef> {
+    let name: String = get_value(path, None, tile, "name")?;
+    let tile_name = Some(name.as_str());
+
+    let atlas: String = get_value(path, tile_name, tile, "atlas")?;
+    let offset: [u32; 2] = get_value(path, tile_name, tile, "offset")?;
+    let is_autotile: bool = get_value(path, tile_name, tile, "is_autotile")?;
+    let autotile_layout = match get_optional::<String>(path, tile_name, tile, "autotile_layout")? {
+        Some(layout) => match AutotileLayout::from_name(&layout) {
+            Some(l) => l,
+            None    => return Err(AtlasError::UnknownLayout { path: path.to_string(), tile: name.clone(), layout: layout }),
+        },
+        None => AutotileLayout::Quadrant,
+    };
+    let tile_kind = match get_optional(path, tile_name, tile, "anim_frames")? {
+        Some(anim_frames) => {
+            let anim_delay = get_value(path, tile_name, tile, "anim_delay")?;
+            if anim_frames == 0 || anim_delay == 0 {
+                return Err(AtlasError::EmptyAnimation { tile: name.clone(), frames: anim_frames, delay: anim_delay });
+            }
+            TileKind::Animated(anim_frames, anim_delay)
+        },
+        None => TileKind::Static,
+    };
+
+    Ok(TileDef {
+        name: name.clone(),
+        atlas: atlas,
+        tile: AtlasTile {
+            offset: (offset[0], offset[1]),
+            is_autotile: is_autotile,
+            autotile_layout: autotile_layout,
+            tile_kind: tile_kind,
+        },
+    })
+}
+
+pub fn get_value<T: Deserialize>(path: &str, tile: Option<&str>, table: &Value, key: &str) -> AtlasResult<T> {
+    util::toml::expect_value_in_table(table, key).map_err(|e| AtlasError::config(path, tile, e))
+}
+
+fn get_optional<T: Deserialize>(path: &str, tile: Option<&str>, table: &Value, key: &str) -> AtlasResult<Option<T>> {
+    util::toml::get_optional_value_in_table(table, key).map_err(|e| AtlasError::config(path, tile, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> TileManagerConfig {
+        let mut names = HashMap::new();
+        names.insert("Water".to_string(), 0);
+
+        TileManagerConfig {
+            names: names,
+            tiles: HashMap::new(),
+            pages: vec!["0.png".to_string()],
+            grid_size: (32, 32),
+            packing: PackingSettings { padding: 4, extrude: 2 },
+            file_hash: "abc".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_cache_roundtrip() {
+        let data = encode_cache(&config()).unwrap();
+        let decoded = decode_cache(&data).unwrap();
+        assert_eq!(decoded.file_hash, "abc");
+        assert_eq!(decoded.names.get("Water"), Some(&0));
+        assert_eq!(decoded.pages, vec!["0.png".to_string()]);
+        assert_eq!(decoded.grid_size, (32, 32));
+        assert_eq!(decoded.packing.padding, 4);
+        assert_eq!(decoded.packing.extrude, 2);
+    }
+
+    #[test]
+    fn test_read_grid_size() {
+        let read = |toml_str: &str| {
+            let val = util::toml::toml_value_from_string(toml_str).unwrap();
+            read_grid_size("test.toml", &val)
+        };
+
+        assert_eq!(read("").unwrap(), DEFAULT_GRID_SIZE);
+        assert_eq!(read("[grid]\ntile_size=[32, 32]").unwrap(), (32, 32));
+        assert!(read("[grid]\ntile_size=[0, 32]").is_err());
+        assert!(read("[grid]\ntile_size=[33, 33]").is_err());
+    }
+
+    #[test]
+    fn test_read_tile_rejects_empty_animations() {
+        let read = |anim: &str| {
+            let toml_str = format!("name=\"Water\"\natlas=\"tiles\"\noffset=[0, 0]\nis_autotile=false\n{}", anim);
+            let val = util::toml::toml_value_from_string(&toml_str).unwrap();
+            read_tile("test.toml", &val)
         };
 
-        for tile in tiles.iter() {
-            let atlas: String = util::toml::expect_value_in_table(&tile, "atlas");
-            let offset: [u32; 2] = util::toml::expect_value_in_table(&tile, "offset");
-            let is_autotile: bool = util::toml::expect_value_in_table(&tile, "is_autotile");
-            let tile_kind = match util::toml::get_value_in_table(&tile, "anim_frames") {
-                Some(anim_frames) => {
-                    let anim_frames = anim_frames.clone().try_into().unwrap();
-                    let anim_delay = util::toml::expect_value_in_table(&tile, "anim_delay");
-                    TileKind::Animated(anim_frames, anim_delay)
-                },
-                None => TileKind::Static,
-            };
-
-            let tile = AtlasTile {
-                offset: (offset[0], offset[1]),
-                is_autotile: is_autotile,
-                tile_kind: tile_kind,
-            };
-
-            let file_path = format!("data/texture/{}", atlas);
-            builder.add_tile(&file_path, idx, tile);
-
-            idx += 1;
+        assert!(read("anim_frames=4\nanim_delay=100").is_ok());
+        match read("anim_frames=0\nanim_delay=100") {
+            Err(AtlasError::EmptyAnimation { frames: 0, .. }) => (),
+            _ => panic!("expected an animation without frames to be rejected"),
+        }
+        match read("anim_frames=4\nanim_delay=0") {
+            Err(AtlasError::EmptyAnimation { delay: 0, .. }) => (),
+            _ => panic!("expected an animation without a delay to be rejected"),
         }
+    }
 
-        let hash = hash_str(toml_str);
+    #[test]
+    fn test_read_tile_reports_unknown_layouts() {
+        let toml_str = "name=\"Wall\"\natlas=\"tiles\"\noffset=[0, 0]\nis_autotile=true\nautotile_layout=\"hex\"";
+        let val = util::toml::toml_value_from_string(toml_str).unwrap();
+        match read_tile("test.toml", &val) {
+            Err(e @ AtlasError::UnknownLayout { .. }) =>
+                assert_eq!(e.to_string(), "test.toml: tile \"Wall\" has unknown autotile layout \"hex\""),
+            _ => panic!("expected an unknown layout error"),
+        }
+    }
 
-        builder.file_hash = hash;
+    #[test]
+    fn test_cache_rejects_other_versions() {
+        let mut data = encode_cache(&config()).unwrap();
+        data[CACHE_MAGIC.len()] = data[CACHE_MAGIC.len()].wrapping_add(1);
+        assert!(decode_cache(&data).is_err());
+    }
 
-        builder.build(display, packed_folder)
+    #[test]
+    fn test_cache_rejects_headerless_data() {
+        let data = bincode::serialize(&config(), bincode::Infinite).unwrap();
+        assert!(decode_cache(&data).is_err());
+        assert!(decode_cache(&[]).is_err());
     }
 }
@@ -0,0 +1,150 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use image::ImageError;
+
+use atlas::TileOffset;
+use autotile::AutotileLayout;
+use util::toml::TomlError;
+
+/// Everything that can go wrong while loading, packing or caching a tile
+/// atlas.
+#[derive(Debug)]
+pub enum AtlasError {
+    /// A file couldn't be read or written.
+    Io(String, io::Error),
+
+    /// An image couldn't be decoded or encoded.
+    Image(String, ImageError),
+
+    /// The atlas config has a missing or malformed key. Carries the name of
+    /// the tile being read, if the problem is inside a tile definition.
+    Config {
+        path: String,
+        tile: Option<String>,
+        error: TomlError,
+    },
+
+    /// A tile uses an autotile layout that doesn't exist.
+    UnknownLayout { path: String, tile: String, layout: String },
+
+    /// A tile refers to a sprite sheet that isn't listed under `maps`.
+    UnknownAtlas { tile: String, atlas: String },
+
+    /// Two tiles were given the same name.
+    DuplicateTile(String),
+
+    /// A tile's offset lies outside its sprite sheet, which is
+    /// `sheet_tiles` tiles across and down.
+    OffsetOutOfBounds { tile: String, offset: TileOffset, sheet_tiles: (u32, u32) },
+
+    /// An autotile's block of tiles runs off the edge of its sprite sheet.
+    AutotileDoesNotFit { tile: String, layout: AutotileLayout, sheet_tiles: (u32, u32) },
+
+    /// The later frames of an animated tile run off the edge of its sprite
+    /// sheet.
+    AnimationOutOfBounds { tile: String, frames: u64, sheet_tiles: (u32, u32) },
+
+    /// An animated tile has no frames, or no delay between them.
+    EmptyAnimation { tile: String, frames: u64, delay: u64 },
+
+    /// An autotile is on a sheet whose tiles, `tile_size` pixels across
+    /// and down, can't be cut into four equal quadrants.
+    OddAutotileSize { tile: String, tile_size: (u32, u32) },
+
+    /// A sprite sheet couldn't be packed onto an atlas page.
+    Pack(String),
+
+    /// An atlas page couldn't be made into a texture.
+    Texture(String, String),
+
+    /// A tile was looked up by a name the atlas config doesn't give any
+    /// tile.
+    UnknownTile(String),
+
+    /// The cached atlas config couldn't be read back or written out.
+    Cache(String, String),
+}
+
+pub type AtlasResult<T> = Result<T, AtlasError>;
+
+impl AtlasError {
+    /// Adds the config file path and tile name to a TOML error.
+    pub fn config(path: &str, tile: Option<&str>, error: TomlError) -> AtlasError {
+        AtlasError::Config {
+            path: path.to_string(),
+            tile: tile.map(|t| t.to_string()),
+            error: error,
+        }
+    }
+}
+
+impl fmt::Display for AtlasError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AtlasError::Io(ref path, ref e) => write!(f, "{}: {}", path, e),
+            AtlasError::Image(ref path, ref e) => write!(f, "{}: {}", path, e),
+            AtlasError::Config { ref path, tile: Some(ref tile), ref error } =>
+                write!(f, "{}: in tile \"{}\": {}", path, tile, error),
+            AtlasError::Config { ref path, tile: None, ref error } =>
+                write!(f, "{}: {}", path, error),
+            AtlasError::UnknownLayout { ref path, ref tile, ref layout } =>
+                write!(f, "{}: tile \"{}\" has unknown autotile layout \"{}\"", path, tile, layout),
+            AtlasError::UnknownAtlas { ref tile, ref atlas } =>
+                write!(f, "tile \"{}\" refers to atlas \"{}\", which isn't in maps", tile, atlas),
+            AtlasError::DuplicateTile(ref name) => write!(f, "duplicate tile name \"{}\"", name),
+            AtlasError::OffsetOutOfBounds { ref tile, offset, sheet_tiles } =>
+                write!(f, "tile \"{}\" has offset {:?}, outside its {}x{} tile sheet",
+                       tile, offset, sheet_tiles.0, sheet_tiles.1),
+            AtlasError::AutotileDoesNotFit { ref tile, layout, sheet_tiles } => {
+                let (w, h) = layout.block_size();
+                write!(f, "autotile \"{}\" needs a {}x{} block for the {:?} layout, which doesn't fit its {}x{} tile sheet",
+                       tile, w, h, layout, sheet_tiles.0, sheet_tiles.1)
+            },
+            AtlasError::AnimationOutOfBounds { ref tile, frames, sheet_tiles } =>
+                write!(f, "the {} animation frames of tile \"{}\" run off its {}x{} tile sheet",
+                       frames, tile, sheet_tiles.0, sheet_tiles.1),
+            AtlasError::EmptyAnimation { ref tile, frames, delay } =>
+                write!(f, "tile \"{}\" has {} animation frames {} ms apart, both must be above 0",
+                       tile, frames, delay),
+            AtlasError::OddAutotileSize { ref tile, tile_size } =>
+                write!(f, "autotile \"{}\" has an odd size of {}x{} pixels; quadrant layouts need even sizes",
+                       tile, tile_size.0, tile_size.1),
+            AtlasError::Pack(ref path) => write!(f, "{}: couldn't pack onto an atlas page", path),
+            AtlasError::Texture(ref path, ref msg) => write!(f, "{}: couldn't make a texture: {}", path, msg),
+            AtlasError::UnknownTile(ref name) => write!(f, "no tile named \"{}\" in the atlas", name),
+            AtlasError::Cache(ref path, ref msg) => write!(f, "{}: bad atlas cache: {}", path, msg),
+        }
+    }
+}
+
+impl Error for AtlasError {
+    fn description(&self) -> &str {
+        match *self {
+            AtlasError::Io(..)                      => "I/O error",
+            AtlasError::Image(..)                   => "image error",
+            AtlasError::Config { .. }               => "invalid atlas config",
+            AtlasError::UnknownLayout { .. }        => "unknown autotile layout",
+            AtlasError::UnknownAtlas { .. }         => "unknown atlas",
+            AtlasError::DuplicateTile(..)           => "duplicate tile name",
+            AtlasError::OffsetOutOfBounds { .. }    => "tile offset out of bounds",
+            AtlasError::AutotileDoesNotFit { .. }   => "autotile block out of bounds",
+            AtlasError::AnimationOutOfBounds { .. } => "animation frames out of bounds",
+            AtlasError::EmptyAnimation { .. }       => "empty animation",
+            AtlasError::OddAutotileSize { .. }      => "odd autotile size",
+            AtlasError::Pack(..)                    => "texture packing failed",
+            AtlasError::Texture(..)                 => "texture creation failed",
+            AtlasError::UnknownTile(..)             => "unknown tile",
+            AtlasError::Cache(..)                   => "bad atlas cache",
+        }
+    }
+
+    fn cause(&self) -> Option<&Error> {
+        match *self {
+            AtlasError::Io(_, ref e)    => Some(e),
+            AtlasError::Image(_, ref e) => Some(e),
+            _                           => None,
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use image::RgbaImage;
+
+/// Rebuilds a sprite sheet so every `cell`-sized cell is surrounded by
+/// `extrude` pixels copied from its own border. Cells are laid out in the
+/// same order, `cell + 2 * extrude` pixels apart, so sampling slightly past
+/// the edge of a cell picks up its own edge color instead of its neighbor.
+///
+/// Partial cells at the right and bottom edges of the sheet are dropped.
+pub fn extrude_cells(image: &RgbaImage, cell: (u32, u32), extrude: u32) -> RgbaImage {
+    let (w, h) = image.dimensions();
+    let (cw, ch) = cell;
+    let cols = w / cw;
+    let rows = h / ch;
+    let (stride_x, stride_y) = (cw + 2 * extrude, ch + 2 * extrude);
+
+    let mut out = RgbaImage::new(cols * stride_x, rows * stride_y);
+
+    for row in 0..rows {
+        for col in 0..cols {
+            for y in 0..stride_y {
+                for x in 0..stride_x {
+                    let sx = clamp(x as i32 - extrude as i32, cw);
+                    let sy = clamp(y as i32 - extrude as i32, ch);
+                    let pixel = *image.get_pixel(col * cw + sx, row * ch + sy);
+                    out.put_pixel(col * stride_x + x, row * stride_y + y, pixel);
+                }
+            }
+        }
+    }
+
+    out
+}
+
+fn clamp(v: i32, size: u32) -> u32 {
+    if v < 0 {
+        0
+    } else if v as u32 >= size {
+        size - 1
+    } else {
+        v as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    fn gray(v: u8) -> Rgba<u8> {
+        Rgba { data: [v, v, v, 255] }
+    }
+
+    #[test]
+    fn test_no_extrusion_is_identity() {
+        let mut image = RgbaImage::new(4, 2);
+        image.put_pixel(3, 1, gray(9));
+
+        let out = extrude_cells(&image, (2, 2), 0);
+        assert_eq!(out.dimensions(), (4, 2));
+        assert_eq!(*out.get_pixel(3, 1), gray(9));
+    }
+
+    #[test]
+    fn test_extrudes_each_cell() {
+        // Two 2x1 cells side by side: [1 2][3 4]
+        let mut image = RgbaImage::new(4, 1);
+        for x in 0..4 {
+            image.put_pixel(x, 0, gray(x as u8 + 1));
+        }
+
+        let out = extrude_cells(&image, (2, 1), 1);
+        assert_eq!(out.dimensions(), (8, 3));
+
+        let row: Vec<u8> = (0..8).map(|x| out.get_pixel(x, 1).data[0]).collect();
+        assert_eq!(row, vec![1, 1, 2, 2, 3, 3, 4, 4]);
+
+        // The rows above and below repeat the cell's edge.
+        assert_eq!(*out.get_pixel(5, 0), gray(3));
+        assert_eq!(*out.get_pixel(6, 2), gray(4));
+    }
+
+    #[test]
+    fn test_drops_partial_cells() {
+        let image = RgbaImage::new(5, 3);
+        let out = extrude_cells(&image, (2, 2), 1);
+        assert_eq!(out.dimensions(), (8, 4));
+    }
+}
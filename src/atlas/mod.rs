@@ -4,18 +4,25 @@ use std::path::Path;
 
 use glium;
 use glium::backend::Facade;
-use image::{self, DynamicImage, GenericImage, Rgba};
+use image::{self, DynamicImage, GenericImage, Rgba, RgbaImage};
 use texture_packer::Rect;
 use texture_packer::SkylinePacker;
 use texture_packer::{TexturePacker, TexturePackerConfig};
-use texture_packer::importer::ImageImporter;
 use texture_packer::exporter::ImageExporter;
 
+use autotile::{AutotileLayout, QuadrantCell};
+
 mod config;
+pub mod error;
+mod extrude;
 pub mod font;
+mod slice;
 pub mod texture_atlas;
+pub mod validate;
 
 use self::config::TileManagerConfig;
+use self::error::{AtlasError, AtlasResult};
+use self::slice::SheetRect;
 
 pub type TileOffset = (u32, u32);
 pub type TileIndex = usize;
@@ -53,256 +60,495 @@ impl From<Rect> for AtlasRect {
 pub struct AtlasTile {
     pub offset: TileOffset,
     pub is_autotile: bool,
+    pub autotile_layout: AutotileLayout,
     pub tile_kind: TileKind,
 }
 
-#[derive(Serialize, Deserialize, Clone)]
-pub struct AtlasFrame {
-    tile_size: (u32, u32),
+/// Size in pixels of a board cell on screen, for atlas configs without a
+/// `[grid]` table.
+pub const DEFAULT_GRID_SIZE: (u32, u32) = (48, 48);
+
+/// How sprite sheets are laid out on the atlas pages. Read from the
+/// optional `[packing]` table of an atlas config.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct PackingSettings {
+    /// Empty pixels left between packed cells.
+    pub padding: u32,
+
+    /// Pixels of each packed cell's border duplicated around it, so
+    /// filtering at the edge of a cell doesn't pick up its neighbors.
+    pub extrude: u32,
+}
+
+impl Default for PackingSettings {
+    fn default() -> Self {
+        PackingSettings {
+            padding: 2,
+            extrude: 1,
+        }
+    }
+}
+
+/// One cell cut out of a sprite sheet and packed onto an atlas page.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AtlasCell {
     texture_idx: usize,
+
+    /// Where the cell is on the page, not counting its extruded border.
     rect: AtlasRect,
-    offsets: HashMap<TileIndex, AtlasTile>,
 }
 
-impl AtlasFrame {
-    pub fn new(texture_idx: usize, rect: Rect, tile_size: (u32, u32)) -> Self {
-        AtlasFrame {
-            tile_size: tile_size,
-            texture_idx: texture_idx,
-            rect: AtlasRect::from(rect),
-            offsets: HashMap::new(),
+/// A tile along with the packed cells it is drawn from.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PackedTile {
+    tile: AtlasTile,
+    tile_size: (u32, u32),
+
+    /// Laid out as `slice::tile_cells` cuts them: the same number of cells
+    /// for each animation frame in turn.
+    cells: Vec<AtlasCell>,
+}
+
+impl PackedTile {
+    /// Gets the number of the current animation frame.
+    fn frame(&self, msecs: u64) -> usize {
+        match self.tile.tile_kind {
+            TileKind::Static                       => 0,
+            TileKind::Animated(frame_count, delay) => ((msecs / delay) % frame_count) as usize,
+        }
+    }
+
+    /// Gets the cells of the current animation frame.
+    fn frame_cells(&self, msecs: u64) -> &[AtlasCell] {
+        let frame = self.frame(msecs);
+        let per_frame = slice::cells_per_frame(&self.tile);
+
+        &self.cells[frame * per_frame..(frame + 1) * per_frame]
+    }
+
+    /// Gets the size in pixels of one quadrant of the tile on its sheet.
+    fn quadrant_size(&self) -> (u32, u32) {
+        (self.tile_size.0 / 2, self.tile_size.1 / 2)
+    }
+
+    /// Gets the cell holding one quadrant of the current animation frame,
+    /// and the quadrant's position in pixels inside that cell.
+    fn quadrant_cell(&self, cell: QuadrantCell, msecs: u64) -> (&AtlasCell, (u32, u32)) {
+        let cells = self.frame_cells(msecs);
+
+        if self.tile.is_autotile {
+            let columns = self.tile.autotile_layout.block_size().0 * 2;
+            (&cells[(cell.1 * columns + cell.0) as usize], (0, 0))
+        } else {
+            let (qw, qh) = self.quadrant_size();
+            (&cells[0], (cell.0 * qw, cell.1 * qh))
         }
     }
 }
 
 pub type TilePacker<'a> = TexturePacker<'a, DynamicImage, SkylinePacker<Rgba<u8>>>;
 
-pub struct TileManager {
+/// Something a packed atlas page can be loaded into, either a GPU texture or
+/// an image kept in memory for rendering without a display.
+pub trait AtlasPage {
+    fn page_dimensions(&self) -> (u32, u32);
+}
+
+impl AtlasPage for Texture2d {
+    fn page_dimensions(&self) -> (u32, u32) {
+        self.dimensions()
+    }
+}
+
+impl AtlasPage for RgbaImage {
+    fn page_dimensions(&self) -> (u32, u32) {
+        self.dimensions()
+    }
+}
+
+pub struct TileManager<P = Texture2d> {
     config: TileManagerConfig,
-    textures: Vec<Texture2d>,
+    textures: Vec<P>,
+}
+
+/// A tile manager whose pages are plain images, for use without a display.
+pub type TileImages = TileManager<RgbaImage>;
+
+/// A sprite sheet waiting to be cut up and packed.
+struct Sheet {
+    image: RgbaImage,
+    tile_size: (u32, u32),
 }
 
 pub struct TileManagerBuilder<'a> {
-    locations: HashMap<TileIndex, String>,
-    frames: HashMap<String, AtlasFrame>,
+    names: HashMap<String, TileIndex>,
+    sheets: HashMap<String, Sheet>,
+    tiles: Vec<(TileIndex, String, AtlasTile)>,
     packers: Vec<TilePacker<'a>>,
+    packing: PackingSettings,
+    grid_size: (u32, u32),
     pub file_hash: String,
 }
 
 impl <'a> TileManagerBuilder<'a> {
-    pub fn new() -> Self {
+    pub fn new(packing: PackingSettings, grid_size: (u32, u32)) -> Self {
         let mut builder = TileManagerBuilder {
-            locations: HashMap::new(),
-            frames: HashMap::new(),
+            names: HashMap::new(),
+            sheets: HashMap::new(),
+            tiles: Vec::new(),
             packers: Vec::new(),
+            packing: packing,
+            grid_size: grid_size,
             file_hash: String::new(),
         };
         builder.add_packer();
         builder
     }
 
-    pub fn add_tile(&mut self, path_str: &str, index: TileIndex, tile_data: AtlasTile) {
-        let key = path_str.to_string();
-        assert!(self.frames.contains_key(&path_str.to_string()));
-
-        {
-            let mut frame = self.frames.get_mut(&key).unwrap();
-            assert!(!frame.offsets.contains_key(&index));
-            frame.offsets.insert(index, tile_data);
-            self.locations.insert(index, key);
+    pub fn add_tile(&mut self, path_str: &str, name: &str, index: TileIndex, tile_data: AtlasTile) -> AtlasResult<()> {
+        let (tile_size, sheet_tiles) = match self.sheets.get(path_str) {
+            Some(sheet) => {
+                let (w, h) = sheet.image.dimensions();
+                (sheet.tile_size, (w / sheet.tile_size.0, h / sheet.tile_size.1))
+            },
+            None => return Err(AtlasError::UnknownAtlas { tile: name.to_string(), atlas: path_str.to_string() }),
+        };
+        if self.names.contains_key(name) {
+            return Err(AtlasError::DuplicateTile(name.to_string()));
         }
-    }
 
-    pub fn add_frame(&mut self, path_string: &str, tile_size: (u32, u32)) {
-        if self.frames.contains_key(path_string) {
-            return;
-        }
+        validate::check_tile(name, &tile_data, tile_size, sheet_tiles)?;
 
-        let path = Path::new(&path_string);
-        let texture = ImageImporter::import_from_file(&path).unwrap();
-
-        for (idx, packer) in self.packers.iter_mut().enumerate() {
-            if packer.can_pack(&texture) {
-                packer.pack_own(path_string.to_string(), texture).unwrap();
-                let rect = packer.get_frame(&path_string).unwrap().frame.clone();
-                self.frames.insert(path_string.to_string(), AtlasFrame::new(idx, rect, tile_size));
-                // cannot return self here, since self already borrowed, so
-                // cannot use builder pattern.
-                return;
-            }
+        self.names.insert(name.to_string(), index);
+        self.tiles.push((index, path_str.to_string(), tile_data));
+
+        Ok(())
+    }
+
+    pub fn add_sheet(&mut self, path_string: &str, tile_size: (u32, u32)) -> AtlasResult<()> {
+        if self.sheets.contains_key(path_string) {
+            return Ok(());
         }
 
-        self.add_packer();
+        let image = image::open(&Path::new(&path_string))
+            .map_err(|e| AtlasError::Image(path_string.to_string(), e))?;
 
-        {
-            // complains that borrow doesn't last long enough
-            // len mut packer = self.newest_packer_mut();
+        self.sheets.insert(path_string.to_string(), Sheet {
+            image: image.to_rgba(),
+            tile_size: tile_size,
+        });
 
-            let packer_idx = self.packers.len() - 1;
-            let mut packer = self.packers.get_mut(packer_idx).unwrap();
-            packer.pack_own(path_string.to_string(), texture).unwrap();
-            let rect = packer.get_frame(&path_string).unwrap().frame.clone();
-            self.frames.insert(path_string.to_string(), AtlasFrame::new(packer_idx, rect, tile_size));
-        }
+        Ok(())
     }
 
     fn add_packer(&mut self) {
-        let config = TexturePackerConfig {
-            max_width: 2048,
-            max_height: 2048,
-            allow_rotation: false,
-            texture_outlines: false,
-            trim: false,
-            texture_padding: 0,
-            ..Default::default()
+        self.packers.push(TexturePacker::new_skyline(packer_config(&self.packing)));
+    }
+
+    /// Cuts a cell out of a sheet and packs it onto the first page with room
+    /// for it, starting a new page if none has.
+    fn pack_cell(&mut self, path_str: &str, rect: SheetRect) -> AtlasResult<AtlasCell> {
+        let extrude = self.packing.extrude;
+        let (x, y, w, h) = rect;
+        let key = format!("{}@{},{}:{}x{}", path_str, x, y, w, h);
+
+        let texture = {
+            let sheet = &self.sheets[path_str];
+            DynamicImage::ImageRgba8(extrude::extrude_cells(&slice::crop(&sheet.image, rect), (w, h), extrude))
         };
 
-        self.packers.push(TexturePacker::new_skyline(config));
+        let mut texture_idx = self.packers.iter().position(|packer| packer.can_pack(&texture));
+        if texture_idx.is_none() {
+            self.add_packer();
+            texture_idx = Some(self.packers.len() - 1);
+        }
+        let texture_idx = texture_idx.unwrap();
+
+        let packed = pack(&mut self.packers[texture_idx], &key, texture)?;
+
+        Ok(AtlasCell {
+            texture_idx: texture_idx,
+            rect: AtlasRect {
+                x: packed.x + extrude,
+                y: packed.y + extrude,
+                w: w,
+                h: h,
+            },
+        })
     }
 
-    pub fn build<F: Facade>(&self, display: &F, packed_tex_folder: &str) -> TileManager {
+    /// Packs the cells of every tile that was added, reusing cells that
+    /// several tiles share.
+    fn pack_tiles(&mut self) -> AtlasResult<HashMap<TileIndex, PackedTile>> {
+        let mut tiles = self.tiles.clone();
+        tiles.sort_by_key(|&(index, _, _)| index);
+
+        let mut packed_cells: HashMap<(String, SheetRect), AtlasCell> = HashMap::new();
+        let mut packed_tiles = HashMap::new();
+
+        for &(index, ref path_str, ref tile) in tiles.iter() {
+            let tile_size = self.sheets[path_str].tile_size;
+            let mut cells = Vec::new();
+
+            for rect in slice::tile_cells(tile, tile_size) {
+                let key = (path_str.clone(), rect);
+                if let Some(cell) = packed_cells.get(&key) {
+                    cells.push(cell.clone());
+                    continue;
+                }
+
+                let cell = self.pack_cell(path_str, rect)?;
+                packed_cells.insert(key, cell.clone());
+                cells.push(cell);
+            }
+
+            packed_tiles.insert(index, PackedTile {
+                tile: tile.clone(),
+                tile_size: tile_size,
+                cells: cells,
+            });
+        }
+
+        Ok(packed_tiles)
+    }
+
+    pub fn build<P, F>(&mut self, packed_tex_folder: &str, make_page: F) -> AtlasResult<TileManager<P>>
+        where F: Fn(DynamicImage) -> Result<P, String> {
+        let packed_tiles = self.pack_tiles()?;
+
         let mut textures = Vec::new();
+        let mut pages = Vec::new();
 
         let packed_folder_path = config::get_config_cache_path(packed_tex_folder);
+        let folder_str = packed_folder_path.display().to_string();
 
         if Path::exists(packed_folder_path.as_path()) {
-            fs::remove_dir_all(packed_folder_path.as_path()).unwrap();
+            fs::remove_dir_all(packed_folder_path.as_path())
+                .map_err(|e| AtlasError::Io(folder_str.clone(), e))?;
         }
 
-        fs::create_dir_all(packed_folder_path.as_path()).unwrap();
+        fs::create_dir_all(packed_folder_path.as_path())
+            .map_err(|e| AtlasError::Io(folder_str.clone(), e))?;
 
         for (idx, packer) in self.packers.iter().enumerate() {
-            let image = ImageExporter::export(packer).unwrap();
-
+            let page = format!("{}.png", idx);
             let mut file_path = packed_folder_path.clone();
-            file_path.push(&format!("{}.png", idx));
+            file_path.push(&page);
+            let file_str = file_path.display().to_string();
 
-            let mut file = File::create(file_path).unwrap();
+            let image = ImageExporter::export(packer).map_err(|_| AtlasError::Pack(file_str.clone()))?;
 
-            image.save(&mut file, image::PNG).unwrap();
-            textures.push(make_texture(display, image));
+            let mut file = File::create(&file_path).map_err(|e| AtlasError::Io(file_str.clone(), e))?;
+
+            image.save(&mut file, image::PNG).map_err(|e| AtlasError::Image(file_str.clone(), e))?;
+            textures.push(make_page(image).map_err(|msg| AtlasError::Texture(file_str.clone(), msg))?);
+            pages.push(page);
         }
 
         println!("Saved {}", packed_tex_folder);
 
         let config = TileManagerConfig {
-            locations: self.locations.clone(),
-            frames: self.frames.clone(),
+            names: self.names.clone(),
+            tiles: packed_tiles,
+            pages: pages,
+            grid_size: self.grid_size,
+            packing: self.packing,
             file_hash: self.file_hash.clone(),
         };
 
-        config::write_tile_manager_config(&config, packed_tex_folder);
+        config::write_tile_manager_config(&config, packed_tex_folder)?;
 
-        TileManager {
+        Ok(TileManager {
             config: config,
             textures: textures,
-        }
+        })
+    }
+}
+
+fn packer_config(packing: &PackingSettings) -> TexturePackerConfig {
+    TexturePackerConfig {
+        max_width: 2048,
+        max_height: 2048,
+        allow_rotation: false,
+        texture_outlines: false,
+        trim: false,
+        texture_padding: packing.padding,
+        ..Default::default()
+    }
+}
+
+/// Describes the packer settings, so a change to them invalidates the cached
+/// atlas pages.
+pub fn packer_settings(packing: &PackingSettings) -> String {
+    let config = packer_config(packing);
+    format!("max_size={}x{} rotation={} outlines={} trim={} padding={} extrude={}",
+            config.max_width, config.max_height, config.allow_rotation,
+            config.texture_outlines, config.trim, config.texture_padding, packing.extrude)
+}
+
+/// Packs a texture onto the page and returns where it ended up.
+fn pack(packer: &mut TilePacker, key: &str, texture: DynamicImage) -> AtlasResult<Rect> {
+    if packer.pack_own(key.to_string(), texture).is_err() {
+        return Err(AtlasError::Pack(key.to_string()));
+    }
+
+    match packer.get_frame(key) {
+        Some(frame) => Ok(frame.frame.clone()),
+        None        => Err(AtlasError::Pack(key.to_string())),
     }
 }
 
-impl TileManager {
-    pub fn new(config: TileManagerConfig, textures: Vec<Texture2d>) -> Self {
+impl<P> TileManager<P> {
+    pub fn new(config: TileManagerConfig, textures: Vec<P>) -> Self {
         TileManager {
             config: config,
             textures: textures,
         }
     }
 
-    fn get_frame(&self, tile_type: TileIndex) -> &AtlasFrame {
-        let tex_name = self.config.locations.get(&tile_type).unwrap();
-        self.config.frames.get(tex_name).unwrap()
+    pub fn get_texture(&self, idx: usize) -> &P {
+        self.textures.get(idx).unwrap()
     }
 
-    pub fn get_tile_texture_idx(&self, tile_type: TileIndex) -> usize {
-        self.get_frame(tile_type).texture_idx
+    pub fn passes(&self) -> usize {
+        self.textures.len()
     }
 
-
-    pub fn get_tilemap_tex_ratio(&self, texture_idx: usize) -> [f32; 2] {
-        let dimensions = self.textures.get(texture_idx).unwrap().dimensions();
-
-        let cols: u32 = dimensions.0 / 24;
-        let rows: u32 = dimensions.1 / 24;
-        [1.0 / cols as f32, 1.0 / rows as f32]
+    /// Gets the size in pixels of a board cell on screen, from the `[grid]`
+    /// table of the atlas config.
+    pub fn grid_size(&self) -> (u32, u32) {
+        self.config.grid_size
     }
 
-    pub fn get_sprite_tex_ratio(&self, tile_type: TileIndex) -> [f32; 2] {
-        let frame = self.get_frame(tile_type);
-        let (mut sx, mut sy) = frame.tile_size;
+    /// Gets the settings the pages were packed with, from the `[packing]`
+    /// table of the atlas config.
+    pub fn packing(&self) -> PackingSettings {
+        self.config.packing
+    }
 
-        if frame.offsets.get(&tile_type).unwrap().is_autotile {
-            // divide the autotile into 24x24 from 48x48
-            sx /= 2;
-            sy /= 2;
+    /// Looks up a tile by the name it was given in the atlas config.
+    pub fn index_of(&self, name: &str) -> AtlasResult<TileIndex> {
+        match self.config.names.get(name) {
+            Some(&idx) => Ok(idx),
+            None       => Err(AtlasError::UnknownTile(name.to_string())),
         }
+    }
 
-        let texture_idx = self.get_frame(tile_type).texture_idx;
-        let dimensions = self.textures.get(texture_idx).unwrap().dimensions();
+    /// Like `index_of`, but panics if there is no such tile.
+    #[cfg(test)]
+    pub fn expect_index_of(&self, name: &str) -> TileIndex {
+        self.index_of(name).unwrap()
+    }
+}
 
-        let cols: f32 = dimensions.0 as f32 / sx as f32;
-        let rows: f32 = dimensions.1 as f32 / sy as f32;
-        [1.0 / cols, 1.0 / rows]
+impl<P: AtlasPage> TileManager<P> {
+    fn get_tile(&self, tile_type: TileIndex) -> &PackedTile {
+        self.config.tiles.get(&tile_type).unwrap()
     }
 
-    pub fn get_tile_texture_size(&self, tile_type: TileIndex) -> (u32, u32) {
-        self.get_frame(tile_type).tile_size
+    fn get_page_dimensions(&self, texture_idx: usize) -> (u32, u32) {
+        self.textures.get(texture_idx).unwrap().page_dimensions()
     }
 
-    pub fn get_texture_offset(&self, tile_type: TileIndex, msecs: u64) -> (f32, f32) {
-        let frame = self.get_frame(tile_type);
-        let tile = frame.offsets.get(&tile_type).unwrap();
+    fn to_uv(&self, texture_idx: usize, px: (u32, u32)) -> (f32, f32) {
+        let dimensions = self.get_page_dimensions(texture_idx);
+        (px.0 as f32 / dimensions.0 as f32, px.1 as f32 / dimensions.1 as f32)
+    }
 
-        let get_tex_coords = |index: (u32, u32)| {
-            let tex_ratio = self.get_sprite_tex_ratio(tile_type);
-            let mut add_offset = get_add_offset(&frame.rect, &frame.tile_size);
+    /// Gets the page holding the tile's current animation frame. Autotile
+    /// quadrants can be spread over several pages, so use
+    /// `get_quadrant_texture_idx` for those.
+    pub fn get_tile_texture_idx(&self, tile_type: TileIndex, msecs: u64) -> usize {
+        self.get_tile(tile_type).frame_cells(msecs)[0].texture_idx
+    }
 
-            match tile.tile_kind {
-                TileKind::Static => (),
-                TileKind::Animated(frame_count, delay) => {
-                    let current_frame = msecs / delay;
-                    let mut x_index_offset = current_frame % frame_count;
+    pub fn get_quadrant_texture_idx(&self, tile_type: TileIndex, cell: QuadrantCell, msecs: u64) -> usize {
+        self.get_tile(tile_type).quadrant_cell(cell, msecs).0.texture_idx
+    }
 
-                    if tile.is_autotile {
-                        x_index_offset *= 2;
-                    }
+    pub fn get_autotile_layout(&self, tile_type: TileIndex) -> Option<AutotileLayout> {
+        let tile = &self.get_tile(tile_type).tile;
 
-                    add_offset.0 += x_index_offset as u32;
-                }
-            }
+        if tile.is_autotile {
+            Some(tile.autotile_layout)
+        } else {
+            None
+        }
+    }
 
-            let mut ratio = 1;
+    /// Checks if the tile has more than one animation frame, so anything
+    /// drawn with it has to be redrawn as time passes.
+    pub fn is_animated(&self, tile_type: TileIndex) -> bool {
+        match self.get_tile(tile_type).tile.tile_kind {
+            TileKind::Animated(..) => true,
+            TileKind::Static       => false,
+        }
+    }
 
-            if tile.is_autotile {
-                ratio = 2;
-            }
+    /// Gets the number of the tile's current animation frame, which is
+    /// always 0 for static tiles.
+    pub fn get_frame(&self, tile_type: TileIndex, msecs: u64) -> usize {
+        self.get_tile(tile_type).frame(msecs)
+    }
 
-            let tx = ((index.0 + add_offset.0) * ratio) as f32 * tex_ratio[0];
-            let ty = ((index.1 + add_offset.1) * ratio) as f32 * tex_ratio[1];
+    /// Gets the size of one quadrant of the tile in texture coordinates on
+    /// the page holding the given quadrant.
+    pub fn get_quadrant_tex_ratio(&self, tile_type: TileIndex, cell: QuadrantCell, msecs: u64) -> [f32; 2] {
+        let texture_idx = self.get_quadrant_texture_idx(tile_type, cell, msecs);
+        let (u, v) = self.to_uv(texture_idx, self.get_quadrant_size(tile_type));
+        [u, v]
+    }
 
-            (tx, ty)
+    pub fn get_sprite_tex_ratio(&self, tile_type: TileIndex, msecs: u64) -> [f32; 2] {
+        let tile = self.get_tile(tile_type);
+        let size = if tile.tile.is_autotile {
+            // autotiles are packed a quadrant at a time
+            tile.quadrant_size()
+        } else {
+            tile.tile_size
         };
 
-        get_tex_coords(tile.offset)
+        let (u, v) = self.to_uv(self.get_tile_texture_idx(tile_type, msecs), size);
+        [u, v]
     }
 
-    pub fn get_texture(&self, idx: usize) -> &Texture2d {
-        self.textures.get(idx).unwrap()
+    pub fn get_tile_texture_size(&self, tile_type: TileIndex) -> (u32, u32) {
+        self.get_tile(tile_type).tile_size
     }
 
-    pub fn passes(&self) -> usize {
-        self.textures.len()
+    /// Gets the size in pixels of one quadrant of the tile on its atlas page,
+    /// which need not match the quadrants of the board grid.
+    pub fn get_quadrant_size(&self, tile_type: TileIndex) -> (u32, u32) {
+        self.get_tile(tile_type).quadrant_size()
+    }
+
+    /// Gets the position in pixels of the top-left corner of the tile's
+    /// current animation frame on its atlas page.
+    pub fn get_texture_offset_px(&self, tile_type: TileIndex, msecs: u64) -> (u32, u32) {
+        let rect = &self.get_tile(tile_type).frame_cells(msecs)[0].rect;
+        (rect.x, rect.y)
+    }
+
+    pub fn get_texture_offset(&self, tile_type: TileIndex, msecs: u64) -> (f32, f32) {
+        let texture_idx = self.get_tile_texture_idx(tile_type, msecs);
+        self.to_uv(texture_idx, self.get_texture_offset_px(tile_type, msecs))
     }
-}
 
-fn get_add_offset(rect: &AtlasRect, tile_size: &(u32, u32)) -> (u32, u32) {
-    let ceil = |a, b| (a + b - 1) / b;
-    let cols: u32 = ceil(rect.x, tile_size.0);
-    let rows: u32 = ceil(rect.y, tile_size.1);
-    (cols, rows)
+    /// Gets the position in pixels of one quadrant inside the tile's current
+    /// animation frame, as picked by the autotile resolver.
+    pub fn get_quadrant_offset_px(&self, tile_type: TileIndex, cell: QuadrantCell, msecs: u64) -> (u32, u32) {
+        let (packed, inner) = self.get_tile(tile_type).quadrant_cell(cell, msecs);
+        (packed.rect.x + inner.0, packed.rect.y + inner.1)
+    }
+
+    pub fn get_quadrant_offset(&self, tile_type: TileIndex, cell: QuadrantCell, msecs: u64) -> (f32, f32) {
+        let texture_idx = self.get_quadrant_texture_idx(tile_type, cell, msecs);
+        self.to_uv(texture_idx, self.get_quadrant_offset_px(tile_type, cell, msecs))
+    }
 }
 
-pub fn make_texture<F: Facade>(display: &F, image: DynamicImage) -> Texture2d {
+pub fn make_texture<F: Facade>(display: &F, image: DynamicImage) -> Result<Texture2d, String> {
     let dimensions = image.dimensions();
     let image = glium::texture::RawImage2d::from_raw_rgba_reversed(image.to_rgba().into_raw(), dimensions);
-    Texture2d::new(display, image).unwrap()
+    Texture2d::new(display, image).map_err(|e| e.to_string())
 }
@@ -0,0 +1,102 @@
+use image::RgbaImage;
+
+use atlas::{AtlasTile, TileKind};
+
+/// A rect in pixels on a source sprite sheet: x, y, width and height.
+pub type SheetRect = (u32, u32, u32, u32);
+
+/// Gets the number of animation frames a tile has.
+pub fn frame_count(tile: &AtlasTile) -> u32 {
+    match tile.tile_kind {
+        TileKind::Static             => 1,
+        TileKind::Animated(count, _) => count as u32,
+    }
+}
+
+/// Gets the number of cells each animation frame of a tile is cut into:
+/// every quadrant of the layout's block for autotiles, or one whole tile.
+pub fn cells_per_frame(tile: &AtlasTile) -> usize {
+    if tile.is_autotile {
+        let (w, h) = tile.autotile_layout.block_size();
+        (w * 2 * h * 2) as usize
+    } else {
+        1
+    }
+}
+
+/// Lists the cells of a sheet with `tile_size` tiles that a tile is drawn
+/// from. Autotiles give each quadrant of their block in rows, one frame
+/// after another; other tiles give one whole tile per frame.
+pub fn tile_cells(tile: &AtlasTile, tile_size: (u32, u32)) -> Vec<SheetRect> {
+    let (sx, sy) = tile_size;
+    let (ox, oy) = tile.offset;
+    let mut cells = Vec::new();
+
+    for frame in 0..frame_count(tile) {
+        if tile.is_autotile {
+            let (bw, bh) = tile.autotile_layout.block_size();
+            let (qw, qh) = (sx / 2, sy / 2);
+            let (base_x, base_y) = ((ox + frame * bw) * sx, oy * sy);
+
+            for qy in 0..(bh * 2) {
+                for qx in 0..(bw * 2) {
+                    cells.push((base_x + qx * qw, base_y + qy * qh, qw, qh));
+                }
+            }
+        } else {
+            cells.push(((ox + frame) * sx, oy * sy, sx, sy));
+        }
+    }
+
+    cells
+}
+
+/// Copies a rect out of a sheet.
+pub fn crop(image: &RgbaImage, rect: SheetRect) -> RgbaImage {
+    let (x, y, w, h) = rect;
+    RgbaImage::from_fn(w, h, |px, py| *image.get_pixel(x + px, y + py))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+    use testing::tile;
+
+    #[test]
+    fn test_plain_tile_cells() {
+        let cells = tile_cells(&tile((1, 2), false, TileKind::Static), (34, 34));
+        assert_eq!(cells, vec![(34, 68, 34, 34)]);
+    }
+
+    #[test]
+    fn test_animated_tile_cells() {
+        let cells = tile_cells(&tile((1, 0), false, TileKind::Animated(3, 100)), (24, 24));
+        assert_eq!(cells, vec![(24, 0, 24, 24), (48, 0, 24, 24), (72, 0, 24, 24)]);
+    }
+
+    #[test]
+    fn test_autotile_cells() {
+        let tile = tile((6, 0), true, TileKind::Animated(2, 100));
+        let cells = tile_cells(&tile, (48, 48));
+
+        // A quadrant block is 2x3 tiles, or 4x6 quadrants.
+        assert_eq!(cells_per_frame(&tile), 24);
+        assert_eq!(cells.len(), 48);
+        assert_eq!(cells[0], (288, 0, 24, 24));
+        assert_eq!(cells[5], (288 + 24, 24, 24, 24));
+        assert_eq!(cells[23], (288 + 72, 120, 24, 24));
+
+        // The second frame starts one block to the right.
+        assert_eq!(cells[24], (384, 0, 24, 24));
+    }
+
+    #[test]
+    fn test_crop() {
+        let image = RgbaImage::from_fn(4, 4, |x, y| Rgba { data: [x as u8, y as u8, 0, 255] });
+        let cropped = crop(&image, (1, 2, 2, 2));
+        assert_eq!(cropped.dimensions(), (2, 2));
+        assert_eq!(cropped.get_pixel(0, 0).data, [1, 2, 0, 255]);
+        assert_eq!(cropped.get_pixel(1, 1).data, [2, 3, 0, 255]);
+    }
+}
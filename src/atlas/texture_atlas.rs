@@ -3,14 +3,15 @@ use std::fs::File;
 use std::path::Path;
 
 use glium::backend::Facade;
-use image::{self, DynamicImage, Rgba};
+use image::{self, DynamicImage, GenericImage, Rgba};
 use texture_packer::Rect;
 use texture_packer::SkylinePacker;
 use texture_packer::{TexturePacker, TexturePackerConfig};
 use texture_packer::importer::ImageImporter;
 use texture_packer::exporter::ImageExporter;
 
-use atlas::{self, AtlasRect, Texture2d};
+use atlas::{self, AtlasRect, PackingSettings, Texture2d};
+use atlas::extrude;
 
 pub struct TextureAtlas {
     texture: Texture2d,
@@ -22,23 +23,25 @@ type TextureAtlasPacker<'a> = TexturePacker<'a, DynamicImage, SkylinePacker<Rgba
 pub struct TextureAtlasBuilder<'a> {
     packer: TextureAtlasPacker<'a>,
     frames: HashMap<String, Rect>,
+    packing: PackingSettings,
 }
 
 impl<'a> TextureAtlasBuilder<'a> {
-    pub fn new() -> Self {
+    pub fn new(packing: PackingSettings) -> Self {
         let config = TexturePackerConfig {
             max_width: 4096,
             max_height: 4096,
             allow_rotation: false,
             texture_outlines: false,
             trim: false,
-            texture_padding: 0,
+            texture_padding: packing.padding,
             ..Default::default()
         };
 
         TextureAtlasBuilder {
             packer: TexturePacker::new_skyline(config),
             frames: HashMap::new(),
+            packing: packing,
         }
     }
 
@@ -46,6 +49,8 @@ impl<'a> TextureAtlasBuilder<'a> {
         let path_str = format!("data/texture/{}.png", &texture_name);
         let path = Path::new(&path_str);
         let texture = ImageImporter::import_from_file(&path).unwrap();
+        let size = texture.dimensions();
+        let texture = DynamicImage::ImageRgba8(extrude::extrude_cells(&texture.to_rgba(), size, self.packing.extrude));
 
         self.packer.pack_own(path_str.to_string(), texture).unwrap();
 
@@ -60,11 +65,16 @@ impl<'a> TextureAtlasBuilder<'a> {
         let mut file = File::create("data/pack.png").unwrap();
         image.save(&mut file, image::PNG).unwrap();
 
-        let texture = atlas::make_texture(display, image);
+        let texture = atlas::make_texture(display, image).unwrap();
 
         let mut frames = HashMap::new();
         for (key, frame) in self.frames.iter() {
-            frames.insert(key.clone(), AtlasRect::from(*frame));
+            let mut rect = AtlasRect::from(*frame);
+            rect.x += self.packing.extrude;
+            rect.y += self.packing.extrude;
+            rect.w -= 2 * self.packing.extrude;
+            rect.h -= 2 * self.packing.extrude;
+            frames.insert(key.clone(), rect);
         }
 
         TextureAtlas {
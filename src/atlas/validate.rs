@@ -0,0 +1,223 @@
+//! Checks an atlas config for mistakes without packing it, so every problem
+//! in `tiles.toml` or `sprites.toml` can be reported at once.
+
+use std::collections::{HashMap, HashSet};
+
+use image::{self, GenericImage};
+use toml::Value;
+
+use atlas::{AtlasTile, TileKind};
+use atlas::config;
+use atlas::error::{AtlasError, AtlasResult};
+use util;
+
+/// Loads the atlas config at `filename` along with the sprite sheets it
+/// names, returning every problem found.
+pub fn validate(filename: &str) -> Vec<AtlasError> {
+    let mut problems = Vec::new();
+
+    let val = match read_toml(filename) {
+        Ok(val) => val,
+        Err(e)  => return vec![e],
+    };
+
+    if let Err(e) = config::read_grid_size(filename, &val) {
+        problems.push(e);
+    }
+
+    // Size of the tiles of each sheet and of the sheet in tiles, or None if
+    // the image couldn't be loaded.
+    let mut sheets = HashMap::new();
+
+    for map in get_array(filename, &val, "maps", &mut problems).iter() {
+        let map = match config::read_map(filename, map) {
+            Ok(map) => map,
+            Err(e)  => { problems.push(e); continue; },
+        };
+
+        let path = config::sheet_path(&map.name);
+        let sheet_tiles = match image::open(&path) {
+            Ok(image) => {
+                let (w, h) = image.dimensions();
+                Some((map.tile_size, (w / map.tile_size.0, h / map.tile_size.1)))
+            },
+            Err(e) => {
+                problems.push(AtlasError::Image(path, e));
+                None
+            },
+        };
+
+        sheets.insert(map.name, sheet_tiles);
+    }
+
+    let mut names = HashSet::new();
+
+    for tile in get_array(filename, &val, "tiles", &mut problems).iter() {
+        let def = match config::read_tile(filename, tile) {
+            Ok(def) => def,
+            Err(e)  => { problems.push(e); continue; },
+        };
+
+        if !names.insert(def.name.clone()) {
+            problems.push(AtlasError::DuplicateTile(def.name.clone()));
+        }
+
+        match sheets.get(&def.atlas) {
+            Some(&Some((tile_size, sheet_tiles))) => {
+                if let Err(e) = check_tile(&def.name, &def.tile, tile_size, sheet_tiles) {
+                    problems.push(e);
+                }
+            },
+            // Already reported when the sheet failed to load.
+            Some(&None) => (),
+            None => problems.push(AtlasError::UnknownAtlas {
+                tile: def.name.clone(),
+                atlas: def.atlas.clone(),
+            }),
+        }
+    }
+
+    problems
+}
+
+/// Checks that a tile, its whole autotile block and all of its animation
+/// frames lie inside a sheet `sheet_tiles` tiles across and down, whose
+/// tiles are `tile_size` pixels. Animations need frames and a delay, and
+/// autotiles an even tile size to cut into quadrants.
+pub fn check_tile(name: &str, tile: &AtlasTile, tile_size: (u32, u32), sheet_tiles: (u32, u32)) -> AtlasResult<()> {
+    let (cols, rows) = sheet_tiles;
+    let (x, y) = tile.offset;
+
+    if let TileKind::Animated(frames, delay) = tile.tile_kind {
+        if frames == 0 || delay == 0 {
+            return Err(AtlasError::EmptyAnimation { tile: name.to_string(), frames: frames, delay: delay });
+        }
+    }
+
+    if tile.is_autotile && (tile_size.0 % 2 != 0 || tile_size.1 % 2 != 0) {
+        return Err(AtlasError::OddAutotileSize { tile: name.to_string(), tile_size: tile_size });
+    }
+
+    if x >= cols || y >= rows {
+        return Err(AtlasError::OffsetOutOfBounds {
+            tile: name.to_string(),
+            offset: tile.offset,
+            sheet_tiles: sheet_tiles,
+        });
+    }
+
+    let block = if tile.is_autotile {
+        tile.autotile_layout.block_size()
+    } else {
+        (1, 1)
+    };
+
+    if tile.is_autotile && (x + block.0 > cols || y + block.1 > rows) {
+        return Err(AtlasError::AutotileDoesNotFit {
+            tile: name.to_string(),
+            layout: tile.autotile_layout,
+            sheet_tiles: sheet_tiles,
+        });
+    }
+
+    if let TileKind::Animated(frames, _) = tile.tile_kind {
+        // Frames are laid out left to right, one block apart.
+        if x as u64 + frames * block.0 as u64 > cols as u64 {
+            return Err(AtlasError::AnimationOutOfBounds {
+                tile: name.to_string(),
+                frames: frames,
+                sheet_tiles: sheet_tiles,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+fn read_toml(filename: &str) -> AtlasResult<Value> {
+    let toml_str = util::toml::toml_string_from_file(filename)
+        .map_err(|e| AtlasError::Io(filename.to_string(), e))?;
+    util::toml::toml_value_from_string(&toml_str)
+        .map_err(|e| AtlasError::config(filename, None, e))
+}
+
+fn get_array(filename: &str, val: &Value, key: &str, problems: &mut Vec<AtlasError>) -> Vec<Value> {
+    match config::get_value(filename, None, val, key) {
+        Ok(array) => array,
+        Err(e)    => { problems.push(e); Vec::new() },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use testing::tile;
+
+    #[test]
+    fn test_tile_in_bounds() {
+        assert!(check_tile("a", &tile((3, 3), false, TileKind::Static), (32, 32), (4, 4)).is_ok());
+        assert!(check_tile("a", &tile((2, 1), true, TileKind::Static), (32, 32), (4, 4)).is_ok());
+        assert!(check_tile("a", &tile((0, 0), false, TileKind::Animated(4, 100)), (32, 32), (4, 4)).is_ok());
+    }
+
+    #[test]
+    fn test_offset_out_of_bounds() {
+        match check_tile("a", &tile((4, 0), false, TileKind::Static), (32, 32), (4, 4)) {
+            Err(AtlasError::OffsetOutOfBounds { .. }) => (),
+            _ => panic!("expected the offset to be out of bounds"),
+        }
+    }
+
+    #[test]
+    fn test_autotile_does_not_fit() {
+        // A quadrant autotile needs a 2x3 block.
+        match check_tile("a", &tile((3, 0), true, TileKind::Static), (32, 32), (4, 4)) {
+            Err(AtlasError::AutotileDoesNotFit { .. }) => (),
+            _ => panic!("expected the autotile not to fit"),
+        }
+        match check_tile("a", &tile((0, 2), true, TileKind::Static), (32, 32), (4, 4)) {
+            Err(AtlasError::AutotileDoesNotFit { .. }) => (),
+            _ => panic!("expected the autotile not to fit"),
+        }
+    }
+
+    #[test]
+    fn test_animation_out_of_bounds() {
+        match check_tile("a", &tile((1, 0), false, TileKind::Animated(4, 100)), (32, 32), (4, 4)) {
+            Err(AtlasError::AnimationOutOfBounds { frames: 4, .. }) => (),
+            _ => panic!("expected the animation to run off the sheet"),
+        }
+        match check_tile("a", &tile((0, 0), true, TileKind::Animated(3, 100)), (32, 32), (4, 4)) {
+            Err(AtlasError::AnimationOutOfBounds { frames: 3, .. }) => (),
+            _ => panic!("expected the animation to run off the sheet"),
+        }
+    }
+
+    #[test]
+    fn test_empty_animation() {
+        for &(frames, delay) in &[(0, 100), (4, 0)] {
+            match check_tile("a", &tile((0, 0), false, TileKind::Animated(frames, delay)), (32, 32), (4, 4)) {
+                Err(AtlasError::EmptyAnimation { .. }) => (),
+                _ => panic!("expected {} frames {} ms apart to be rejected", frames, delay),
+            }
+        }
+    }
+
+    #[test]
+    fn test_odd_autotile_size() {
+        match check_tile("a", &tile((0, 0), true, TileKind::Static), (32, 31), (4, 4)) {
+            Err(AtlasError::OddAutotileSize { tile_size: (32, 31), .. }) => (),
+            _ => panic!("expected an autotile with odd tiles to be rejected"),
+        }
+        // Plain tiles aren't split into quadrants.
+        assert!(check_tile("a", &tile((0, 0), false, TileKind::Static), (31, 31), (4, 4)).is_ok());
+    }
+
+    #[test]
+    fn test_shipped_configs_are_valid() {
+        for filename in &["data/tiles.toml", "data/sprites.toml"] {
+            let problems = validate(filename);
+            assert!(problems.is_empty(), "{}: {:?}", filename, problems);
+        }
+    }
+}
@@ -0,0 +1,511 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use board::{Board, LayerView};
+use point::{self, Direction, Point};
+use point::Direction::*;
+use terrain::TerrainId;
+
+/// Anything laid out on a grid of tiles that can be compared with their
+/// neighbors to decide which autotile pieces to draw.
+pub trait AutotileGrid {
+    type Tile: Eq + Hash;
+
+    fn tile_at(&self, pos: &Point) -> Self::Tile;
+
+    fn in_bounds(&self, pos: &Point) -> bool;
+}
+
+impl AutotileGrid for Board {
+    type Tile = TerrainId;
+
+    fn tile_at(&self, pos: &Point) -> TerrainId {
+        self.get(pos)
+    }
+
+    fn in_bounds(&self, pos: &Point) -> bool {
+        Board::in_bounds(self, pos)
+    }
+}
+
+impl<'a> AutotileGrid for LayerView<'a> {
+    type Tile = TerrainId;
+
+    fn tile_at(&self, pos: &Point) -> TerrainId {
+        self.get(pos)
+    }
+
+    fn in_bounds(&self, pos: &Point) -> bool {
+        LayerView::in_bounds(self, pos)
+    }
+}
+
+struct ConnectionGroup {
+    connects_to: Vec<String>,
+    out_of_bounds: bool,
+}
+
+/// Decides which neighboring tiles an autotile blends into.
+///
+/// Tiles always connect to tiles of the same kind. Tiles can also be put in
+/// named groups, which connect to every other tile in the group and to the
+/// members of any groups listed in `connects_to`. Groups with
+/// `out_of_bounds` set treat the edge of the map as connected.
+pub struct ConnectionRules<T: Eq + Hash> {
+    group_of: HashMap<T, String>,
+    groups: HashMap<String, ConnectionGroup>,
+}
+
+impl<T: Eq + Hash> ConnectionRules<T> {
+    pub fn new() -> Self {
+        ConnectionRules {
+            group_of: HashMap::new(),
+            groups: HashMap::new(),
+        }
+    }
+
+    pub fn add_group(&mut self, name: &str, members: Vec<T>, connects_to: Vec<String>, out_of_bounds: bool) {
+        for member in members.into_iter() {
+            self.add_member(name, member);
+        }
+
+        self.groups.insert(name.to_string(), ConnectionGroup {
+            connects_to: connects_to,
+            out_of_bounds: out_of_bounds,
+        });
+    }
+
+    /// Puts `tile` in the named group. The group connects its members even if
+    /// it was never added with `add_group`.
+    pub fn add_member(&mut self, name: &str, tile: T) {
+        self.group_of.insert(tile, name.to_string());
+    }
+
+    /// Whether `tile` blends into `neighbor`, where `None` is a position off
+    /// the edge of the map.
+    pub fn connects(&self, tile: &T, neighbor: Option<&T>) -> bool {
+        let group = self.group_of.get(tile).and_then(|name| self.groups.get(name));
+
+        let neighbor = match neighbor {
+            Some(n) => n,
+            None    => return group.map_or(false, |g| g.out_of_bounds),
+        };
+
+        if tile == neighbor {
+            return true;
+        }
+
+        match (self.group_of.get(tile), self.group_of.get(neighbor)) {
+            (Some(mine), Some(theirs)) => {
+                mine == theirs || group.map_or(false, |g| g.connects_to.contains(theirs))
+            },
+            _ => false,
+        }
+    }
+}
+
+/// How the pieces of an autotile are arranged on its sheet.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AutotileLayout {
+    /// RPG Maker style: a block of 2x3 tiles, each cut into quadrants that
+    /// are picked separately.
+    Quadrant,
+
+    /// One whole tile for each of the 47 distinct 8-neighbor masks, eight
+    /// tiles to a row.
+    Blob,
+
+    /// One whole tile for each of the 16 combinations of the 4 cardinal
+    /// neighbors, four tiles to a row.
+    Wang,
+}
+
+impl AutotileLayout {
+    pub fn from_name(name: &str) -> Option<AutotileLayout> {
+        match name {
+            "quadrant" => Some(AutotileLayout::Quadrant),
+            "blob"     => Some(AutotileLayout::Blob),
+            "wang"     => Some(AutotileLayout::Wang),
+            _          => None,
+        }
+    }
+
+    /// Size in tiles of the block holding one animation frame of the
+    /// autotile.
+    pub fn block_size(&self) -> (u32, u32) {
+        match *self {
+            AutotileLayout::Quadrant => (2, 3),
+            AutotileLayout::Blob     => (8, 6),
+            AutotileLayout::Wang     => (4, 4),
+        }
+    }
+}
+
+/// Position of a quadrant within its tile's block on the sheet, measured in
+/// quadrants.
+pub type QuadrantCell = (u32, u32);
+
+pub type Quadrant = i8;
+
+pub const QUAD_NW: Quadrant = 0;
+pub const QUAD_NE: Quadrant = 1;
+pub const QUAD_SW: Quadrant = 2;
+pub const QUAD_SE: Quadrant = 3;
+
+pub static QUADRANTS: [Quadrant; 4] = [QUAD_NW, QUAD_NE, QUAD_SW, QUAD_SE];
+
+/// The bit in an edge mask that is set when the neighbor in `dir` connects.
+pub fn dir_to_bit(dir: Direction) -> u8 {
+    match dir {
+        Direction::NE => 0,
+        Direction::N  => 1,
+        Direction::NW => 2,
+        Direction::E  => 3,
+        Direction::W  => 4,
+        Direction::SE => 5,
+        Direction::S  => 6,
+        Direction::SW => 7,
+    }
+}
+
+pub fn is_connected(edges: u8, dir: Direction) -> bool {
+    (edges & (1 << dir_to_bit(dir))) > 0
+}
+
+/// Builds the 8-neighbor edge mask for the tile at `pos`. A bit is set for
+/// every neighbor the tile connects to under `rules`.
+pub fn get_neighboring_edges<G: AutotileGrid>(grid: &G, pos: Point, rules: &ConnectionRules<G::Tile>) -> u8 {
+    let my_type = grid.tile_at(&pos);
+
+    let mut res: u8 = 0;
+    for dir in point::DIRECTIONS.iter() {
+        let new_pos = pos + *dir;
+        let neighbor = if grid.in_bounds(&new_pos) {
+            Some(grid.tile_at(&new_pos))
+        } else {
+            None
+        };
+
+        if rules.connects(&my_type, neighbor.as_ref()) {
+            res |= 1 << dir_to_bit(*dir);
+        }
+    }
+    res
+}
+
+/// Returns the index of the sub-tile to draw in the given quadrant, counting
+/// left to right, top to bottom through the 4x6 grid of quadrants making up
+/// an RPG Maker-style autotile.
+pub fn get_autotile_index(edges: u8, quadrant: Quadrant) -> i8 {
+    let is_connected = |dir: Direction| is_connected(edges, dir);
+
+    if !is_connected(N) && !is_connected(W) && !is_connected(E) && !is_connected(S) {
+        let ret = match quadrant {
+            QUAD_NW => {
+                0
+            },
+            QUAD_NE => {
+                1
+            },
+            QUAD_SW => {
+                4
+            },
+            QUAD_SE => {
+                5
+            },
+            _ => -1,
+        };
+        return ret;
+    }
+
+    // The tiles are in order from the corner inside.
+    let lookup_idx = |horiz: Direction, vert: Direction, corner: Direction, tiles: [i8; 4], corner_piece: i8| {
+        if !is_connected(horiz) && !is_connected(vert) {
+            tiles[0]
+        } else if !is_connected(horiz) && is_connected(vert) {
+            tiles[1]
+        } else if is_connected(horiz) && !is_connected(vert) {
+            tiles[2]
+        } else {
+            if !is_connected(corner) {
+                corner_piece
+            } else {
+                tiles[3]
+            }
+        }
+    };
+
+    match quadrant {
+        QUAD_NW => {
+            lookup_idx(N, W, NW, [8, 9, 12, 13], 2)
+        },
+        QUAD_NE => {
+            lookup_idx(N, E, NE, [11, 10, 15, 14], 3)
+        },
+        QUAD_SW => {
+            lookup_idx(S, W, SW, [20, 21, 16, 17], 6)
+        },
+        QUAD_SE => {
+            lookup_idx(S, E, SE, [23, 22, 19, 18], 7)
+        },
+        _ => -1,
+    }
+}
+
+// Every 8-neighbor mask that can occur once corners without both adjacent
+// sides are dropped, in ascending order using N=1, NE=2, E=4, SE=8, S=16,
+// SW=32, W=64, NW=128. A blob tile's position on the sheet is its mask's
+// index in this list.
+static BLOB_MASKS: [u8; 47] = [
+    0, 1, 4, 5, 7, 16, 17, 20, 21, 23, 28, 29, 31, 64, 65, 68,
+    69, 71, 80, 81, 84, 85, 87, 92, 93, 95, 112, 113, 116, 117, 119, 124,
+    125, 127, 193, 197, 199, 209, 213, 215, 221, 223, 241, 245, 247, 253, 255,
+];
+
+/// Gets the index of the tile to use out of a 47-tile blob set.
+pub fn get_blob_index(edges: u8) -> u32 {
+    let is_connected = |dir: Direction| is_connected(edges, dir);
+
+    let bit = |dir: Direction, value: u8| if is_connected(dir) { value } else { 0 };
+
+    let mut mask = bit(N, 1) | bit(E, 4) | bit(S, 16) | bit(W, 64);
+
+    // Corners only matter when both of the sides next to them connect.
+    if is_connected(N) && is_connected(E) { mask |= bit(NE, 2); }
+    if is_connected(S) && is_connected(E) { mask |= bit(SE, 8); }
+    if is_connected(S) && is_connected(W) { mask |= bit(SW, 32); }
+    if is_connected(N) && is_connected(W) { mask |= bit(NW, 128); }
+
+    BLOB_MASKS.iter().position(|m| *m == mask).unwrap() as u32
+}
+
+/// Gets the index of the tile to use out of a 16-tile Wang edge set, with
+/// N=1, E=2, S=4 and W=8.
+pub fn get_wang_index(edges: u8) -> u32 {
+    let mut index = 0;
+    for (i, dir) in [N, E, S, W].iter().enumerate() {
+        if is_connected(edges, *dir) {
+            index |= 1 << i;
+        }
+    }
+    index
+}
+
+/// Cells to use for a tile that isn't an autotile, which is drawn whole.
+pub static PLAIN_QUADRANT_CELLS: [QuadrantCell; 4] = [(0, 0), (1, 0), (0, 1), (1, 1)];
+
+/// Finds which quadrant of the autotile's block to draw in each quadrant of
+/// the tile, in the order NW, NE, SW, SE.
+pub fn get_quadrant_cells(layout: AutotileLayout, edges: u8) -> [QuadrantCell; 4] {
+    let mut res = [(0, 0); 4];
+
+    match layout {
+        AutotileLayout::Quadrant => {
+            for (i, quadrant) in QUADRANTS.iter().enumerate() {
+                let index = get_autotile_index(edges, *quadrant) as u32;
+                res[i] = (index % 4, index / 4);
+            }
+        },
+        AutotileLayout::Blob | AutotileLayout::Wang => {
+            let (index, cols) = match layout {
+                AutotileLayout::Blob => (get_blob_index(edges), 8),
+                _                    => (get_wang_index(edges), 4),
+            };
+            let (tx, ty) = (index % cols, index / cols);
+            for (i, cell) in PLAIN_QUADRANT_CELLS.iter().enumerate() {
+                res[i] = (tx * 2 + cell.0, ty * 2 + cell.1);
+            }
+        },
+    }
+
+    res
+}
+
+/// Resolves the sub-tile indices of all four quadrants of the tile at `pos`,
+/// in the order NW, NE, SW, SE.
+pub fn get_autotile_indices<G: AutotileGrid>(grid: &G, pos: Point, rules: &ConnectionRules<G::Tile>) -> [i8; 4] {
+    let edges = get_neighboring_edges(grid, pos, rules);
+    let mut res = [0; 4];
+    for (i, quadrant) in QUADRANTS.iter().enumerate() {
+        res[i] = get_autotile_index(edges, *quadrant);
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use point::Point;
+    use super::*;
+
+    struct TestGrid {
+        rows: Vec<Vec<char>>,
+    }
+
+    impl TestGrid {
+        fn new(rows: &[&str]) -> Self {
+            TestGrid {
+                rows: rows.iter().map(|r| r.chars().collect()).collect(),
+            }
+        }
+    }
+
+    impl AutotileGrid for TestGrid {
+        type Tile = char;
+
+        fn tile_at(&self, pos: &Point) -> char {
+            if !self.in_bounds(pos) {
+                return ' ';
+            }
+            self.rows[pos.y as usize][pos.x as usize]
+        }
+
+        fn in_bounds(&self, pos: &Point) -> bool {
+            pos.x >= 0 && pos.y >= 0 &&
+                (pos.y as usize) < self.rows.len() &&
+                (pos.x as usize) < self.rows[pos.y as usize].len()
+        }
+    }
+
+    fn indices(grid: &TestGrid, x: i32, y: i32) -> [i8; 4] {
+        get_autotile_indices(grid, Point::new(x, y), &ConnectionRules::new())
+    }
+
+    #[test]
+    fn test_isolated() {
+        let grid = TestGrid::new(&["...",
+                                   ".#.",
+                                   "..."]);
+        assert_eq!(get_neighboring_edges(&grid, Point::new(1, 1), &ConnectionRules::new()), 0);
+        assert_eq!(indices(&grid, 1, 1), [0, 1, 4, 5]);
+    }
+
+    #[test]
+    fn test_surrounded() {
+        let grid = TestGrid::new(&["###",
+                                   "###",
+                                   "###"]);
+        assert_eq!(get_neighboring_edges(&grid, Point::new(1, 1), &ConnectionRules::new()), 0xFF);
+        assert_eq!(indices(&grid, 1, 1), [13, 14, 17, 18]);
+    }
+
+    #[test]
+    fn test_horizontal_line() {
+        let grid = TestGrid::new(&["...",
+                                   "###",
+                                   "..."]);
+        assert_eq!(indices(&grid, 1, 1), [9, 10, 21, 22]);
+    }
+
+    #[test]
+    fn test_vertical_line() {
+        let grid = TestGrid::new(&[".#.",
+                                   ".#.",
+                                   ".#."]);
+        assert_eq!(indices(&grid, 1, 1), [12, 15, 16, 19]);
+    }
+
+    #[test]
+    fn test_inner_corner() {
+        let grid = TestGrid::new(&[".##",
+                                   "###",
+                                   "###"]);
+        assert_eq!(indices(&grid, 1, 1), [2, 14, 17, 18]);
+    }
+
+    #[test]
+    fn test_blob_index() {
+        assert_eq!(get_blob_index(0), 0);
+        assert_eq!(get_blob_index(0xFF), 46);
+
+        // The corner is dropped unless both of its sides connect.
+        let north = 1 << dir_to_bit(N);
+        let north_east = north | (1 << dir_to_bit(NE));
+        assert_eq!(get_blob_index(north), get_blob_index(north_east));
+    }
+
+    #[test]
+    fn test_wang_index() {
+        let grid = TestGrid::new(&[".#.",
+                                   "##.",
+                                   "..."]);
+        let edges = get_neighboring_edges(&grid, Point::new(1, 1), &ConnectionRules::new());
+        assert_eq!(get_wang_index(edges), 1 | 8);
+    }
+
+    #[test]
+    fn test_quadrant_cells() {
+        let cells = get_quadrant_cells(AutotileLayout::Quadrant, 0);
+        assert_eq!(cells, [(0, 0), (1, 0), (0, 1), (1, 1)]);
+
+        let cells = get_quadrant_cells(AutotileLayout::Blob, 0xFF);
+        assert_eq!(cells, [(12, 10), (13, 10), (12, 11), (13, 11)]);
+
+        let cells = get_quadrant_cells(AutotileLayout::Wang, 0xFF);
+        assert_eq!(cells, [(6, 6), (7, 6), (6, 7), (7, 7)]);
+    }
+
+    #[test]
+    fn test_connection_groups() {
+        let grid = TestGrid::new(&["...",
+                                   "+#+",
+                                   "..."]);
+        let mut rules = ConnectionRules::new();
+        rules.add_group("wall", vec!['#', '+'], Vec::new(), false);
+
+        assert_eq!(get_autotile_indices(&grid, Point::new(1, 1), &rules), [9, 10, 21, 22]);
+        assert!(rules.connects(&'+', Some(&'#')));
+        assert!(!rules.connects(&'.', Some(&'#')));
+    }
+
+    #[test]
+    fn test_one_way_connection() {
+        let mut rules = ConnectionRules::new();
+        rules.add_group("water", vec!['~'], vec!["shore".to_string()], false);
+        rules.add_group("shore", vec![','], Vec::new(), false);
+
+        assert!(rules.connects(&'~', Some(&',')));
+        assert!(!rules.connects(&',', Some(&'~')));
+    }
+
+    #[test]
+    fn test_out_of_bounds() {
+        let grid = TestGrid::new(&["##",
+                                   "##"]);
+        let mut rules = ConnectionRules::new();
+        rules.add_group("wall", vec!['#'], Vec::new(), true);
+
+        assert_eq!(get_neighboring_edges(&grid, Point::new(0, 0), &rules), 0xFF);
+        assert!(!ConnectionRules::new().connects(&'#', None));
+    }
+
+    #[test]
+    fn test_edge_of_grid() {
+        let grid = TestGrid::new(&["##",
+                                   "##"]);
+        assert_eq!(indices(&grid, 0, 0), [8, 10, 16, 18]);
+    }
+
+    #[test]
+    fn test_layers_are_autotiled_separately() {
+        use std::rc::Rc;
+        use board::Layer;
+        use terrain::{TerrainDef, TerrainRegistry};
+
+        let mut registry = TerrainRegistry::new();
+        let carpet = registry.add(TerrainDef {
+            name: "Carpet".to_string(),
+            glyph: '=',
+            tile: None,
+            blocking: false,
+            group: None,
+        });
+
+        // Carpet all over the ground, but only one cell of it on the overlay.
+        let mut board = Board::new(Rc::new(registry), 3, 3, carpet);
+        board.set_in(Layer::Overlay, &Point::new(1, 1), carpet).unwrap();
+
+        let rules = ConnectionRules::new();
+        assert_eq!(get_neighboring_edges(&board.layer(Layer::Ground), Point::new(1, 1), &rules), 0xFF);
+        assert_eq!(get_neighboring_edges(&board.layer(Layer::Overlay), Point::new(1, 1), &rules), 0);
+    }
+}
@@ -0,0 +1,42 @@
+//! Checks atlas configs for mistakes and prints every problem found.
+//!
+//! Usage: `cargo run --bin validate [data/tiles.toml ...]`. With no
+//! arguments both of the game's atlas configs are checked. Exits with a
+//! nonzero status if any problems were found.
+
+extern crate autotile;
+
+use std::env;
+use std::process;
+
+use autotile::atlas::validate;
+
+const DEFAULT_CONFIGS: [&'static str; 2] = ["data/tiles.toml", "data/sprites.toml"];
+
+fn main() {
+    let mut files: Vec<String> = env::args().skip(1).collect();
+    if files.is_empty() {
+        files = DEFAULT_CONFIGS.iter().map(|f| f.to_string()).collect();
+    }
+
+    let mut failed = false;
+
+    for file in files.iter() {
+        let problems = validate::validate(file);
+
+        if problems.is_empty() {
+            println!("{}: ok", file);
+            continue;
+        }
+
+        failed = true;
+        println!("{}: {} problem(s)", file, problems.len());
+        for problem in problems.iter() {
+            println!("    {}", problem);
+        }
+    }
+
+    if failed {
+        process::exit(1);
+    }
+}
@@ -0,0 +1,152 @@
+//! Reading and writing boards as ASCII art, one glyph per cell, so boards
+//! for tests can be written by hand.
+
+use std::rc::Rc;
+
+use board::{Board, BoardError, BoardResult};
+use point::Point;
+use terrain::{Feature, TerrainId, TerrainRegistry, TERRAIN_NOTHING};
+
+/// Extra glyphs that stand for a feature on top of some terrain, on top of
+/// the terrain glyphs from the registry.
+#[derive(Clone, Default)]
+pub struct Legend {
+    entries: Vec<(char, Feature, TerrainId)>,
+}
+
+impl Legend {
+    pub fn new() -> Self {
+        Legend {
+            entries: Vec::new(),
+        }
+    }
+
+    /// Makes `glyph` stand for `feature` on `terrain`. Legend glyphs take
+    /// precedence over terrain glyphs.
+    pub fn add(&mut self, glyph: char, feature: Feature, terrain: TerrainId) {
+        self.entries.push((glyph, feature, terrain));
+    }
+
+    fn find(&self, glyph: char) -> Option<(&Feature, TerrainId)> {
+        self.entries.iter()
+            .find(|&&(g, _, _)| g == glyph)
+            .map(|&(_, ref feature, terrain)| (feature, terrain))
+    }
+
+    fn glyph_for(&self, feature: &Feature, terrain: TerrainId) -> Option<char> {
+        self.entries.iter()
+            .find(|&&(_, ref f, t)| f == feature && t == terrain)
+            .map(|&(glyph, _, _)| glyph)
+    }
+}
+
+impl Board {
+    /// Parses a board drawn with terrain glyphs and the glyphs in `legend`,
+    /// one line per row. Short lines are padded with `TERRAIN_NOTHING`.
+    pub fn parse_ascii(terrains: Rc<TerrainRegistry>, text: &str, legend: &Legend) -> BoardResult<Board> {
+        let lines: Vec<&str> = text.lines().collect();
+        let width = lines.iter().map(|l| l.chars().count()).max().unwrap_or(0);
+        let mut board = Board::new(terrains.clone(), width as i32, lines.len() as i32, TERRAIN_NOTHING);
+
+        for (y, line) in lines.iter().enumerate() {
+            for (x, glyph) in line.chars().enumerate() {
+                let pos = Point::new(x as i32, y as i32);
+
+                if let Some((feature, terrain)) = legend.find(glyph) {
+                    board.set(&pos, terrain)?;
+                    board.set_feature(&pos, feature.clone())?;
+                    continue;
+                }
+
+                match terrains.find_by_glyph(glyph) {
+                    Some(terrain) => board.set(&pos, terrain)?,
+                    None          => return Err(BoardError::UnknownGlyph(glyph, pos)),
+                }
+            }
+        }
+
+        Ok(board)
+    }
+
+    /// Draws the board as `parse_ascii` reads it, with features drawn using
+    /// `legend` where it has a glyph for them.
+    pub fn to_ascii(&self, legend: &Legend) -> String {
+        let mut out = String::new();
+
+        for y in 0..self.height() {
+            for x in 0..self.width() {
+                let pos = Point::new(x, y);
+                let terrain = self.get(&pos);
+                let glyph = self.feature_at(&pos)
+                    .and_then(|feature| legend.glyph_for(feature, terrain))
+                    .unwrap_or(self.terrains.get(terrain).glyph);
+                out.push(glyph);
+            }
+            out.push('\n');
+        }
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use terrain::DoorState;
+    use testing::terrains;
+
+    fn legend(terrains: &TerrainRegistry) -> Legend {
+        let floor = terrains.find("Floor").unwrap();
+        let mut legend = Legend::new();
+        legend.add('+', Feature::Door(DoorState::Closed), floor);
+        legend.add('>', Feature::DownStair, floor);
+        legend
+    }
+
+    #[test]
+    fn test_parse_rows() {
+        let board = Board::parse_ascii(terrains(), "#..\n##.\n", &Legend::new()).unwrap();
+        assert_eq!((board.width(), board.height()), (3, 2));
+        assert_eq!(board.terrain_at(&Point::new(2, 0)).name, "Floor");
+        assert_eq!(board.terrain_at(&Point::new(1, 1)).name, "Wall");
+    }
+
+    #[test]
+    fn test_display_prints_rows() {
+        let text = "#..\n##.\n";
+        let board = Board::parse_ascii(terrains(), text, &Legend::new()).unwrap();
+        assert_eq!(board.to_string(), text);
+    }
+
+    #[test]
+    fn test_pads_short_lines() {
+        let board = Board::parse_ascii(terrains(), "###\n#\n", &Legend::new()).unwrap();
+        assert_eq!(board.get(&Point::new(2, 1)), TERRAIN_NOTHING);
+        assert_eq!(board.to_string(), "###\n#  \n");
+    }
+
+    #[test]
+    fn test_legend_roundtrip() {
+        let terrains = terrains();
+        let legend = legend(&terrains);
+        let text = "#+#\n#.>\n";
+
+        let board = Board::parse_ascii(terrains.clone(), text, &legend).unwrap();
+        assert_eq!(board.terrain_at(&Point::new(1, 0)).name, "Floor");
+        assert_eq!(board.feature_at(&Point::new(1, 0)), Some(&Feature::Door(DoorState::Closed)));
+        assert_eq!(board.feature_at(&Point::new(2, 1)), Some(&Feature::DownStair));
+
+        assert_eq!(board.to_ascii(&legend), text);
+
+        // Without the legend features aren't drawn.
+        assert_eq!(board.to_string(), "#.#\n#..\n");
+    }
+
+    #[test]
+    fn test_unknown_glyph() {
+        match Board::parse_ascii(terrains(), "#.\n.?\n", &Legend::new()) {
+            Err(BoardError::UnknownGlyph('?', pos)) => assert_eq!(pos, Point::new(1, 1)),
+            _ => panic!("expected an unknown glyph error"),
+        }
+    }
+}
@@ -0,0 +1,324 @@
+//! Boards are stored as square chunks of cells, which are only allocated
+//! once something is placed in them or they are loaded from a
+//! `ChunkSource`. This keeps very large boards cheap when most of them is
+//! the fill terrain or hasn't been visited yet.
+
+use std::fs;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use board::{Board, BoardError, BoardResult, Layer, LAYERS};
+use point::Point;
+use terrain::{Feature, TerrainId, TerrainRegistry, TERRAIN_NOTHING};
+
+/// Width and height of a chunk in cells.
+pub const CHUNK_SIZE: i32 = 32;
+
+/// Gets the position of the chunk holding the cell at `pt`. Chunk (0, 0)
+/// holds the cells from (0, 0) up to (CHUNK_SIZE - 1, CHUNK_SIZE - 1).
+pub fn chunk_of(pt: &Point) -> Point {
+    Point::new(div_floor(pt.x, CHUNK_SIZE), div_floor(pt.y, CHUNK_SIZE))
+}
+
+/// Gets the position of the top left cell of a chunk.
+pub fn chunk_origin(chunk: &Point) -> Point {
+    Point::new(chunk.x * CHUNK_SIZE, chunk.y * CHUNK_SIZE)
+}
+
+fn div_floor(a: i32, b: i32) -> i32 {
+    if a < 0 {
+        (a - b + 1) / b
+    } else {
+        a / b
+    }
+}
+
+/// The cells of one chunk of a board.
+#[derive(Clone, Debug)]
+pub struct Chunk {
+    /// The terrain of each layer, row by row, in `LAYERS` order.
+    layers: Vec<Vec<TerrainId>>,
+
+    /// Features in the chunk, by position within it.
+    features: Vec<(Point, Feature)>,
+}
+
+impl Chunk {
+    /// Makes a chunk with the ground filled with `fill` and the other layers
+    /// empty.
+    pub fn new(fill: TerrainId) -> Self {
+        let size = (CHUNK_SIZE * CHUNK_SIZE) as usize;
+        let layers = LAYERS.iter().map(|layer| {
+            let terrain = if *layer == Layer::Ground { fill } else { TERRAIN_NOTHING };
+            vec![terrain; size]
+        }).collect();
+
+        Chunk {
+            layers: layers,
+            features: Vec::new(),
+        }
+    }
+
+    fn index(local: &Point) -> Option<usize> {
+        if local.x < 0 || local.y < 0 || local.x >= CHUNK_SIZE || local.y >= CHUNK_SIZE {
+            None
+        } else {
+            Some((local.y * CHUNK_SIZE + local.x) as usize)
+        }
+    }
+
+    /// Gets the terrain at `local`, a position within the chunk.
+    pub fn get(&self, layer: Layer, local: &Point) -> TerrainId {
+        match Chunk::index(local) {
+            Some(idx) => self.layers[layer.index()][idx],
+            None      => TERRAIN_NOTHING,
+        }
+    }
+
+    /// Sets the terrain at `local`, a position within the chunk.
+    pub fn set(&mut self, layer: Layer, local: &Point, val: TerrainId) {
+        if let Some(idx) = Chunk::index(local) {
+            self.layers[layer.index()][idx] = val;
+        }
+    }
+
+    /// Places a feature at `local`, to be put on the board when the chunk is
+    /// loaded.
+    pub fn add_feature(&mut self, local: &Point, feature: Feature) {
+        if Chunk::index(local).is_some() {
+            self.features.push((*local, feature));
+        }
+    }
+
+    pub fn features(&self) -> &[(Point, Feature)] {
+        &self.features
+    }
+
+    pub fn is_layer_empty(&self, layer: Layer) -> bool {
+        self.layers[layer.index()].iter().all(|&terrain| terrain == TERRAIN_NOTHING)
+    }
+}
+
+/// Where a board gets the chunks it hasn't loaded yet, either by generating
+/// them or by reading them from disk.
+pub trait ChunkSource {
+    /// Gets the chunk at `chunk`, or `None` to leave it filled with the
+    /// board's fill terrain.
+    fn load_chunk(&mut self, chunk: &Point) -> BoardResult<Option<Chunk>>;
+}
+
+/// Chunks can be generated by a closure.
+impl<F> ChunkSource for F where F: FnMut(&Point) -> Option<Chunk> {
+    fn load_chunk(&mut self, chunk: &Point) -> BoardResult<Option<Chunk>> {
+        Ok(self(chunk))
+    }
+}
+
+/// Reads chunks from a folder of board files, one per chunk, as written by
+/// `ChunkDir::save`. Chunks without a file are left to the fill terrain.
+pub struct ChunkDir {
+    terrains: Rc<TerrainRegistry>,
+    path: PathBuf,
+}
+
+impl ChunkDir {
+    pub fn new(terrains: Rc<TerrainRegistry>, path: &str) -> Self {
+        ChunkDir {
+            terrains: terrains,
+            path: PathBuf::from(path),
+        }
+    }
+
+    fn chunk_path(&self, chunk: &Point) -> String {
+        self.path.join(format!("{}_{}.board", chunk.x, chunk.y)).display().to_string()
+    }
+
+    /// Writes one chunk of `board` to the folder, creating the folder if
+    /// needed.
+    pub fn save(&self, board: &Board, chunk: &Point) -> BoardResult<()> {
+        fs::create_dir_all(&self.path)
+            .map_err(|e| BoardError::Io(self.path.display().to_string(), e))?;
+
+        let origin = chunk_origin(chunk);
+        let mut part = Board::new(board.terrains().clone(), CHUNK_SIZE, CHUNK_SIZE, TERRAIN_NOTHING);
+
+        for y in 0..CHUNK_SIZE {
+            for x in 0..CHUNK_SIZE {
+                let local = Point::new(x, y);
+                let pos = origin + local;
+                for &layer in LAYERS.iter() {
+                    part.set_in(layer, &local, board.get_in(layer, &pos))?;
+                }
+                if let Some(feature) = board.feature_at(&pos) {
+                    part.set_feature(&local, feature.clone())?;
+                }
+            }
+        }
+
+        part.save(&self.chunk_path(chunk))
+    }
+}
+
+impl ChunkSource for ChunkDir {
+    fn load_chunk(&mut self, chunk: &Point) -> BoardResult<Option<Chunk>> {
+        let path = self.chunk_path(chunk);
+        if !fs::metadata(&path).is_ok() {
+            return Ok(None);
+        }
+
+        let part = Board::load(self.terrains.clone(), &path)?;
+        if part.width() != CHUNK_SIZE || part.height() != CHUNK_SIZE {
+            return Err(BoardError::Format(path, format!("chunk is {}x{}, expected {}x{}",
+                                                        part.width(), part.height(), CHUNK_SIZE, CHUNK_SIZE)));
+        }
+
+        let mut loaded = Chunk::new(TERRAIN_NOTHING);
+        for y in 0..CHUNK_SIZE {
+            for x in 0..CHUNK_SIZE {
+                let local = Point::new(x, y);
+                for &layer in LAYERS.iter() {
+                    loaded.set(layer, &local, part.get_in(layer, &local));
+                }
+            }
+        }
+        for (pos, feature) in part.features() {
+            loaded.add_feature(pos, feature.clone());
+        }
+
+        Ok(Some(loaded))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use testing::{terrains, TempDir};
+
+    #[test]
+    fn test_chunk_of() {
+        assert_eq!(chunk_of(&Point::new(0, 0)), Point::new(0, 0));
+        assert_eq!(chunk_of(&Point::new(CHUNK_SIZE - 1, CHUNK_SIZE)), Point::new(0, 1));
+        assert_eq!(chunk_of(&Point::new(-1, -CHUNK_SIZE)), Point::new(-1, -1));
+        assert_eq!(chunk_of(&Point::new(-CHUNK_SIZE - 1, 0)), Point::new(-2, 0));
+        assert_eq!(chunk_origin(&Point::new(2, -1)), Point::new(2 * CHUNK_SIZE, -CHUNK_SIZE));
+    }
+
+    #[test]
+    fn test_chunks_are_allocated_lazily() {
+        let terrains = terrains();
+        let wall = terrains.find("Wall").unwrap();
+        let floor = terrains.find("Floor").unwrap();
+        let mut board = Board::new(terrains, 2000, 2000, wall);
+        assert_eq!(board.loaded_chunks(), 0);
+        assert_eq!(board.get(&Point::new(1999, 1999)), wall);
+
+        board.set(&Point::new(1000, 1000), floor).unwrap();
+        assert_eq!(board.loaded_chunks(), 1);
+        assert_eq!(board.get(&Point::new(1000, 1000)), floor);
+        assert_eq!(board.get(&Point::new(1001, 1000)), wall);
+        assert!(!board.is_layer_empty(Layer::Ground));
+        assert!(board.is_layer_empty(Layer::Roof));
+    }
+
+    #[test]
+    fn test_generated_chunks() {
+        let terrains = terrains();
+        let wall = terrains.find("Wall").unwrap();
+        let floor = terrains.find("Floor").unwrap();
+
+        // Floor in every chunk with an even x, nothing in the rest.
+        let source = move |chunk: &Point| {
+            if chunk.x % 2 == 0 { Some(Chunk::new(floor)) } else { None }
+        };
+        let mut board = Board::with_source(terrains, 100, 40, wall, Box::new(source));
+
+        board.load_area(&Point::new(0, 0), &Point::new(40, 10)).unwrap();
+        assert_eq!(board.loaded_chunks(), 1);
+        assert_eq!(board.get(&Point::new(5, 5)), floor);
+        assert_eq!(board.get(&Point::new(CHUNK_SIZE + 5, 5)), wall);
+        assert_eq!(board.take_dirty().len(), (CHUNK_SIZE * CHUNK_SIZE) as usize);
+
+        // Loading again changes nothing.
+        board.load_area(&Point::new(0, 0), &Point::new(40, 10)).unwrap();
+        assert!(!board.is_dirty());
+
+        board.unload_chunk(&Point::new(0, 0));
+        assert_eq!(board.loaded_chunks(), 0);
+        assert_eq!(board.get(&Point::new(5, 5)), wall);
+    }
+
+    #[test]
+    fn test_chunk_dir_roundtrip() {
+        let terrains = terrains();
+        let wall = terrains.find("Wall").unwrap();
+        let floor = terrains.find("Floor").unwrap();
+        let dir = TempDir::new("chunk-roundtrip");
+
+        let mut board = Board::new(terrains.clone(), 100, 100, wall);
+        let pos = Point::new(CHUNK_SIZE + 3, 4);
+        board.set(&pos, floor).unwrap();
+        board.set_in(Layer::Roof, &pos, wall).unwrap();
+        board.set_feature(&pos, Feature::UpStair).unwrap();
+        ChunkDir::new(terrains.clone(), &dir.path()).save(&board, &Point::new(1, 0)).unwrap();
+
+        let source = ChunkDir::new(terrains.clone(), &dir.path());
+        let mut loaded = Board::with_source(terrains, 100, 100, wall, Box::new(source));
+        loaded.load_area(&Point::new(0, 0), &Point::new(100, 10)).unwrap();
+
+        assert_eq!(loaded.loaded_chunks(), 1);
+        assert_eq!(loaded.get(&pos), floor);
+        assert_eq!(loaded.get_in(Layer::Roof, &pos), wall);
+        assert_eq!(loaded.feature_at(&pos), Some(&Feature::UpStair));
+        assert_eq!(loaded.get(&Point::new(3, 4)), wall);
+    }
+
+    #[test]
+    fn test_set_loads_chunk_first() {
+        let terrains = terrains();
+        let wall = terrains.find("Wall").unwrap();
+        let floor = terrains.find("Floor").unwrap();
+        let dir = TempDir::new("chunk-set");
+
+        let mut board = Board::new(terrains.clone(), 100, 100, wall);
+        board.set(&Point::new(3, 4), floor).unwrap();
+        board.set_feature(&Point::new(3, 4), Feature::DownStair).unwrap();
+        ChunkDir::new(terrains.clone(), &dir.path()).save(&board, &Point::new(0, 0)).unwrap();
+
+        // Setting a cell before the chunk is loaded keeps what was saved in
+        // the rest of it.
+        let source = ChunkDir::new(terrains.clone(), &dir.path());
+        let mut loaded = Board::with_source(terrains, 100, 100, wall, Box::new(source));
+        loaded.set(&Point::new(5, 5), floor).unwrap();
+        loaded.load_chunk(&Point::new(0, 0)).unwrap();
+
+        assert_eq!(loaded.get(&Point::new(3, 4)), floor);
+        assert_eq!(loaded.feature_at(&Point::new(3, 4)), Some(&Feature::DownStair));
+        assert_eq!(loaded.get(&Point::new(5, 5)), floor);
+        assert_eq!(loaded.get(&Point::new(6, 5)), wall);
+    }
+
+    #[test]
+    fn test_set_feature_loads_chunk_first() {
+        let terrains = terrains();
+        let wall = terrains.find("Wall").unwrap();
+        let dir = TempDir::new("chunk-feature");
+
+        let mut board = Board::new(terrains.clone(), 100, 100, wall);
+        board.set_feature(&Point::new(3, 4), Feature::DownStair).unwrap();
+        ChunkDir::new(terrains.clone(), &dir.path()).save(&board, &Point::new(0, 0)).unwrap();
+
+        // Changing features before the chunk is loaded isn't undone by
+        // loading it, and is saved along with it.
+        let source = ChunkDir::new(terrains.clone(), &dir.path());
+        let mut loaded = Board::with_source(terrains.clone(), 100, 100, wall, Box::new(source));
+        loaded.set_feature(&Point::new(5, 5), Feature::UpStair).unwrap();
+        assert_eq!(loaded.remove_feature(&Point::new(3, 4)).unwrap(), Some(Feature::DownStair));
+        assert_eq!(loaded.loaded_chunks(), 1);
+        ChunkDir::new(terrains.clone(), &dir.path()).save(&loaded, &Point::new(0, 0)).unwrap();
+        loaded.unload_chunk(&Point::new(0, 0));
+
+        loaded.load_chunk(&Point::new(0, 0)).unwrap();
+        assert_eq!(loaded.feature_at(&Point::new(3, 4)), None);
+        assert_eq!(loaded.feature_at(&Point::new(5, 5)), Some(&Feature::UpStair));
+    }
+}
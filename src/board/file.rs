@@ -0,0 +1,627 @@
+//! Saving and loading boards.
+//!
+//! Boards are saved as bincode for the game, or as TOML when the file name
+//! ends in `.toml` so maps can be read and edited by hand. Both formats hold
+//! the same `BoardFile`: the dimensions, a palette of the terrain names used
+//! on the board, each layer that has anything on it as rows of palette
+//! indices, the features, and the cells the player has explored.
+//!
+//! Terrain is stored by name rather than by `TerrainId`, so a board stays
+//! valid when terrain is added to or reordered in `terrain.toml`.
+
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::rc::Rc;
+
+use bincode;
+use toml;
+
+use board::{Board, Layer, LAYERS};
+use point::Point;
+use terrain::{Feature, TerrainId, TerrainRegistry, TERRAIN_NOTHING};
+use util;
+use util::header;
+
+/// Written at the start of binary board files, followed by `BOARD_VERSION`.
+const BOARD_MAGIC: &'static [u8] = b"BORD";
+
+/// Bump this whenever `BoardFile` changes shape, so boards saved in an older
+/// format are upgraded or rejected instead of being misread.
+pub const BOARD_VERSION: u32 = 3;
+
+/// The version before explored cells were saved, which is still read.
+const BOARD_VERSION_2: u32 = 2;
+
+/// The version before boards had layers, which is still read into the
+/// ground layer.
+const BOARD_VERSION_1: u32 = 1;
+
+/// Everything that can go wrong while saving or loading a board.
+#[derive(Debug)]
+pub enum BoardError {
+    /// A file couldn't be read or written.
+    Io(String, io::Error),
+
+    /// The file couldn't be encoded or decoded as a board.
+    Format(String, String),
+
+    /// The file was saved in a different version of the format.
+    Version(String, u32),
+
+    /// The file uses terrain that isn't in the registry.
+    UnknownTerrain(String, String),
+
+    /// The file has a feature of a kind that doesn't exist.
+    UnknownFeature(String, String),
+
+    /// A Tiled map uses a tile id that isn't mapped to any terrain.
+    UnknownTile(String, u32),
+
+    /// An ASCII board has a glyph that is neither a terrain glyph nor in the
+    /// legend.
+    UnknownGlyph(char, Point),
+}
+
+pub type BoardResult<T> = Result<T, BoardError>;
+
+impl fmt::Display for BoardError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BoardError::Io(ref path, ref e) => write!(f, "{}: {}", path, e),
+            BoardError::Format(ref path, ref msg) => write!(f, "{}: invalid board: {}", path, msg),
+            BoardError::Version(ref path, version) =>
+                write!(f, "{}: board is version {}, expected {}", path, version, BOARD_VERSION),
+            BoardError::UnknownTerrain(ref path, ref name) => write!(f, "{}: unknown terrain \"{}\"", path, name),
+            BoardError::UnknownFeature(ref path, ref name) => write!(f, "{}: unknown feature \"{}\"", path, name),
+            BoardError::UnknownTile(ref path, gid) =>
+                write!(f, "{}: tile id {} has no \"terrain\" or \"tile\" property naming a known terrain", path, gid),
+            BoardError::UnknownGlyph(glyph, pos) => write!(f, "unknown glyph '{}' at {}", glyph, pos),
+        }
+    }
+}
+
+impl Error for BoardError {
+    fn description(&self) -> &str {
+        match *self {
+            BoardError::Io(..)             => "I/O error",
+            BoardError::Format(..)         => "invalid board",
+            BoardError::Version(..)        => "unsupported board version",
+            BoardError::UnknownTerrain(..) => "unknown terrain",
+            BoardError::UnknownFeature(..) => "unknown feature",
+            BoardError::UnknownTile(..)    => "unmapped tile id",
+            BoardError::UnknownGlyph(..)   => "unknown glyph",
+        }
+    }
+
+    fn cause(&self) -> Option<&Error> {
+        match *self {
+            BoardError::Io(_, ref e) => Some(e),
+            _                        => None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct BoardFile {
+    version: u32,
+    width: i32,
+    height: i32,
+
+    /// Names of the terrain used on the board, which the layers index into.
+    terrain: Vec<String>,
+
+    /// The explored cells, as runs along each row. Comes before the layers
+    /// because TOML can't have an empty array after tables.
+    explored: Vec<ExploredRun>,
+
+    /// The ground and whichever other layers aren't empty.
+    layers: Vec<LayerDef>,
+
+    features: Vec<FeatureDef>,
+}
+
+/// A `BoardFile` as saved in version 2, without explored cells.
+#[derive(Serialize, Deserialize, Debug)]
+struct BoardFileV2 {
+    version: u32,
+    width: i32,
+    height: i32,
+    terrain: Vec<String>,
+    layers: Vec<LayerDef>,
+    features: Vec<FeatureDef>,
+}
+
+impl BoardFileV2 {
+    fn upgrade(self) -> BoardFile {
+        BoardFile {
+            version: BOARD_VERSION,
+            width: self.width,
+            height: self.height,
+            terrain: self.terrain,
+            explored: Vec::new(),
+            layers: self.layers,
+            features: self.features,
+        }
+    }
+}
+
+/// A `BoardFile` as saved in version 1, with a single layer of cells.
+#[derive(Serialize, Deserialize, Debug)]
+struct BoardFileV1 {
+    version: u32,
+    width: i32,
+    height: i32,
+    terrain: Vec<String>,
+    rows: Vec<Vec<u16>>,
+    features: Vec<FeatureDef>,
+}
+
+impl BoardFileV1 {
+    fn upgrade(self) -> BoardFile {
+        let ground = LayerDef {
+            name: Layer::Ground.name().to_string(),
+            rows: self.rows,
+        };
+
+        BoardFile {
+            version: BOARD_VERSION,
+            width: self.width,
+            height: self.height,
+            terrain: self.terrain,
+            explored: Vec::new(),
+            layers: vec![ground],
+            features: self.features,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct LayerDef {
+    name: String,
+
+    /// The cells of the layer, top row first.
+    rows: Vec<Vec<u16>>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct FeatureDef {
+    x: i32,
+    y: i32,
+    kind: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct ExploredRun {
+    x: i32,
+    y: i32,
+    len: i32,
+}
+
+/// Turns a set of cells into runs of adjacent cells along rows, top row
+/// first.
+fn explored_runs<'a, I: Iterator<Item=&'a Point>>(cells: I) -> Vec<ExploredRun> {
+    let mut cells: Vec<&Point> = cells.collect();
+    cells.sort_by_key(|pt| (pt.y, pt.x));
+
+    let mut runs: Vec<ExploredRun> = Vec::new();
+    for pt in cells {
+        if let Some(run) = runs.last_mut() {
+            if run.y == pt.y && run.x + run.len == pt.x {
+                run.len += 1;
+                continue;
+            }
+        }
+        runs.push(ExploredRun { x: pt.x, y: pt.y, len: 1 });
+    }
+    runs
+}
+
+impl Board {
+    /// Saves the board to `path`, as TOML if it ends in `.toml` and as
+    /// bincode otherwise.
+    ///
+    /// Chunks that haven't been loaded from the board's source are saved as
+    /// the fill terrain; use `ChunkDir` to save streamed boards chunk by
+    /// chunk.
+    pub fn save(&self, path: &str) -> BoardResult<()> {
+        let file = self.to_file();
+        let data = if is_toml(path) {
+            encode_toml(&file).map_err(|msg| BoardError::Format(path.to_string(), msg))?
+        } else {
+            encode_binary(&file).map_err(|msg| BoardError::Format(path.to_string(), msg))?
+        };
+
+        File::create(path)
+            .and_then(|mut f| f.write_all(&data))
+            .map_err(|e| BoardError::Io(path.to_string(), e))
+    }
+
+    /// Loads a board saved by `save`, looking its terrain up by name in
+    /// `terrains`.
+    pub fn load(terrains: Rc<TerrainRegistry>, path: &str) -> BoardResult<Board> {
+        let mut data = Vec::new();
+        File::open(path)
+            .and_then(|mut f| f.read_to_end(&mut data))
+            .map_err(|e| BoardError::Io(path.to_string(), e))?;
+
+        let file = if is_toml(path) {
+            decode_toml(path, &data)?
+        } else {
+            decode_binary(path, &data)?
+        };
+
+        Board::from_file(terrains, path, file)
+    }
+
+    fn to_file(&self) -> BoardFile {
+        let mut terrain = Vec::new();
+        let mut palette: HashMap<TerrainId, u16> = HashMap::new();
+        let mut layers = Vec::new();
+
+        for &layer in LAYERS.iter() {
+            if layer != Layer::Ground && self.is_layer_empty(layer) {
+                continue;
+            }
+
+            let mut rows = Vec::new();
+            for y in 0..self.height() {
+                let mut row = Vec::new();
+                for x in 0..self.width() {
+                    let id = self.get_in(layer, &Point::new(x, y));
+                    let idx = *palette.entry(id).or_insert_with(|| {
+                        terrain.push(self.terrains.get(id).name.clone());
+                        (terrain.len() - 1) as u16
+                    });
+                    row.push(idx);
+                }
+                rows.push(row);
+            }
+
+            layers.push(LayerDef {
+                name: layer.name().to_string(),
+                rows: rows,
+            });
+        }
+
+        let mut features: Vec<FeatureDef> = self.features()
+            .map(|(pos, feature)| FeatureDef { x: pos.x, y: pos.y, kind: feature.name().to_string() })
+            .collect();
+        features.sort_by_key(|f| (f.y, f.x));
+
+        BoardFile {
+            version: BOARD_VERSION,
+            width: self.width(),
+            height: self.height(),
+            terrain: terrain,
+            explored: explored_runs(self.explored()),
+            layers: layers,
+            features: features,
+        }
+    }
+
+    fn from_file(terrains: Rc<TerrainRegistry>, path: &str, file: BoardFile) -> BoardResult<Board> {
+        let invalid = |msg: String| BoardError::Format(path.to_string(), msg);
+
+        if file.version != BOARD_VERSION {
+            return Err(BoardError::Version(path.to_string(), file.version));
+        }
+        if file.width < 0 || file.height < 0 {
+            return Err(invalid(format!("invalid size {}x{}", file.width, file.height)));
+        }
+
+        let mut palette = Vec::new();
+        for name in file.terrain.iter() {
+            match terrains.find(name) {
+                Some(id) => palette.push(id),
+                None     => return Err(BoardError::UnknownTerrain(path.to_string(), name.clone())),
+            }
+        }
+
+        let mut board = Board::new(terrains, file.width, file.height, TERRAIN_NOTHING);
+
+        for def in file.layers.iter() {
+            let layer = match Layer::from_name(&def.name) {
+                Some(layer) => layer,
+                None        => return Err(invalid(format!("unknown layer \"{}\"", def.name))),
+            };
+            if def.rows.len() != file.height as usize {
+                return Err(invalid(format!("{} layer has {} rows, expected {}", def.name, def.rows.len(), file.height)));
+            }
+
+            for (y, row) in def.rows.iter().enumerate() {
+                if row.len() != file.width as usize {
+                    return Err(invalid(format!("row {} of the {} layer has {} cells, expected {}",
+                                               y, def.name, row.len(), file.width)));
+                }
+                for (x, &idx) in row.iter().enumerate() {
+                    let id = match palette.get(idx as usize) {
+                        Some(&id) => id,
+                        None      => return Err(invalid(format!("row {} of the {} layer refers to terrain {}, but only {} are listed",
+                                                                y, def.name, idx, palette.len()))),
+                    };
+                    board.set_in(layer, &Point::new(x as i32, y as i32), id)?;
+                }
+            }
+        }
+
+        for def in file.features.iter() {
+            let pos = Point::new(def.x, def.y);
+            if !board.in_bounds(&pos) {
+                return Err(invalid(format!("feature at ({}, {}) is off the board", def.x, def.y)));
+            }
+            match Feature::from_name(&def.kind) {
+                Some(feature) => board.set_feature(&pos, feature)?,
+                None          => return Err(BoardError::UnknownFeature(path.to_string(), def.kind.clone())),
+            }
+        }
+
+        let mut explored = HashSet::new();
+        for run in file.explored.iter() {
+            if run.len < 1 {
+                return Err(invalid(format!("explored run at ({}, {}) has length {}", run.x, run.y, run.len)));
+            }
+            let last_x = match run.x.checked_add(run.len - 1) {
+                Some(x) => x,
+                None    => return Err(invalid(format!("explored run at ({}, {}) is too long", run.x, run.y))),
+            };
+            let (first, last) = (Point::new(run.x, run.y), Point::new(last_x, run.y));
+            if !board.in_bounds(&first) || !board.in_bounds(&last) {
+                return Err(invalid(format!("explored cells from {} to {} are off the board", first, last)));
+            }
+            explored.extend((0..run.len).map(|i| Point::new(run.x + i, run.y)));
+        }
+        board.explore(&explored);
+
+        Ok(board)
+    }
+}
+
+fn is_toml(path: &str) -> bool {
+    Path::new(path).extension().and_then(|ext| ext.to_str()) == Some("toml")
+}
+
+fn encode_binary(file: &BoardFile) -> Result<Vec<u8>, String> {
+    let mut data = header::write_header(BOARD_MAGIC, BOARD_VERSION);
+
+    let body = bincode::serialize(file, bincode::Infinite).map_err(|e| e.to_string())?;
+    data.extend_from_slice(&body);
+    Ok(data)
+}
+
+fn decode_binary(path: &str, data: &[u8]) -> BoardResult<BoardFile> {
+    let (version, body) = match header::read_header(BOARD_MAGIC, data) {
+        Some(header) => header,
+        None         => return Err(BoardError::Format(path.to_string(), "not a board file".to_string())),
+    };
+
+    let file = match version {
+        BOARD_VERSION   => bincode::deserialize(body),
+        BOARD_VERSION_2 => bincode::deserialize::<BoardFileV2>(body).map(|file| file.upgrade()),
+        BOARD_VERSION_1 => bincode::deserialize::<BoardFileV1>(body).map(|file| file.upgrade()),
+        _               => return Err(BoardError::Version(path.to_string(), version)),
+    };
+    file.map_err(|e| BoardError::Format(path.to_string(), e.to_string()))
+}
+
+fn encode_toml(file: &BoardFile) -> Result<Vec<u8>, String> {
+    toml::to_string(file).map(|s| s.into_bytes()).map_err(|e| e.to_string())
+}
+
+fn decode_toml(path: &str, data: &[u8]) -> BoardResult<BoardFile> {
+    let invalid = |msg: String| BoardError::Format(path.to_string(), msg);
+
+    let text = String::from_utf8_lossy(data);
+    let val = util::toml::toml_value_from_string(&text).map_err(|e| invalid(e.to_string()))?;
+
+    // Check the version first, so an old board is reported as such instead
+    // of as a missing key.
+    let version: u32 = util::toml::expect_value_in_table(&val, "version").map_err(|e| invalid(e.to_string()))?;
+    match version {
+        BOARD_VERSION   => val.try_into::<BoardFile>().map_err(|e| invalid(e.to_string())),
+        BOARD_VERSION_2 => val.try_into::<BoardFileV2>().map(|file| file.upgrade()).map_err(|e| invalid(e.to_string())),
+        BOARD_VERSION_1 => val.try_into::<BoardFileV1>().map(|file| file.upgrade()).map_err(|e| invalid(e.to_string())),
+        _               => Err(BoardError::Version(path.to_string(), version)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use board::Legend;
+    use terrain::DoorState;
+    use testing::terrains;
+
+    fn board() -> Board {
+        let terrains = terrains();
+        let floor = terrains.find("Floor").unwrap();
+        let mut board = Board::new(terrains.clone(), 3, 2, terrains.find("Wall").unwrap());
+        board.set(&Point::new(1, 0), floor).unwrap();
+        board.set(&Point::new(2, 1), floor).unwrap();
+        board.set_feature(&Point::new(1, 0), Feature::Door(DoorState::Closed)).unwrap();
+        board.set_feature(&Point::new(2, 1), Feature::DownStair).unwrap();
+        board.set_feature(&Point::new(0, 1), Feature::Door(DoorState::Open)).unwrap();
+        let explored = vec![Point::new(0, 0), Point::new(1, 0), Point::new(2, 1)];
+        board.explore(&explored.into_iter().collect());
+        board
+    }
+
+    fn assert_same(a: &Board, b: &Board) {
+        assert_eq!((a.width(), a.height()), (b.width(), b.height()));
+        for y in 0..a.height() {
+            for x in 0..a.width() {
+                let pos = Point::new(x, y);
+                for &layer in LAYERS.iter() {
+                    assert_eq!(a.terrain_in(layer, &pos).name, b.terrain_in(layer, &pos).name);
+                }
+            }
+        }
+        assert_eq!(a.features, b.features);
+        assert_eq!(a.explored, b.explored);
+    }
+
+    #[test]
+    fn test_binary_roundtrip() {
+        let board = board();
+        let data = encode_binary(&board.to_file()).unwrap();
+        let file = decode_binary("test", &data).unwrap();
+        assert_same(&board, &Board::from_file(terrains(), "test", file).unwrap());
+    }
+
+    #[test]
+    fn test_toml_roundtrip() {
+        let board = board();
+        let data = encode_toml(&board.to_file()).unwrap();
+        let file = decode_toml("test.toml", &data).unwrap();
+        assert_same(&board, &Board::from_file(terrains(), "test.toml", file).unwrap());
+    }
+
+    #[test]
+    fn test_terrain_is_saved_by_name() {
+        let file = board().to_file();
+        assert_eq!(file.terrain, vec!["Wall".to_string(), "Floor".to_string()]);
+        assert_eq!(file.layers.len(), 1);
+        assert_eq!(file.layers[0].name, "ground");
+        assert_eq!(file.layers[0].rows, vec![vec![0, 1, 0], vec![0, 0, 1]]);
+    }
+
+    #[test]
+    fn test_explored_runs() {
+        let cells = vec![Point::new(3, 1), Point::new(0, 0), Point::new(2, 1), Point::new(1, 0), Point::new(5, 1)];
+        assert_eq!(explored_runs(cells.iter()), vec![
+            ExploredRun { x: 0, y: 0, len: 2 },
+            ExploredRun { x: 2, y: 1, len: 2 },
+            ExploredRun { x: 5, y: 1, len: 1 },
+        ]);
+    }
+
+    #[test]
+    fn test_rejects_explored_cells_off_the_board() {
+        let mut file = board().to_file();
+        file.explored.push(ExploredRun { x: 1, y: 1, len: 3 });
+        assert!(Board::from_file(terrains(), "test", file).is_err());
+    }
+
+    #[test]
+    fn test_rejects_overflowing_explored_runs() {
+        let mut file = board().to_file();
+        file.explored.push(ExploredRun { x: 1, y: 0, len: ::std::i32::MAX });
+        match Board::from_file(terrains(), "test", file) {
+            Err(BoardError::Format(..)) => (),
+            _ => panic!("expected a format error"),
+        }
+    }
+
+    #[test]
+    fn test_layers_roundtrip() {
+        let mut board = board();
+        let wall = board.terrains.find("Wall").unwrap();
+        board.set_in(Layer::Roof, &Point::new(0, 0), wall).unwrap();
+
+        let file = board.to_file();
+        let names: Vec<&str> = file.layers.iter().map(|l| l.name.as_str()).collect();
+        assert_eq!(names, vec!["ground", "roof"]);
+
+        let data = encode_toml(&file).unwrap();
+        let file = decode_toml("test.toml", &data).unwrap();
+        let loaded = Board::from_file(terrains(), "test.toml", file).unwrap();
+        assert_same(&board, &loaded);
+        assert!(loaded.is_layer_empty(Layer::Overlay));
+    }
+
+    #[test]
+    fn test_rejects_unknown_layers() {
+        let mut file = board().to_file();
+        file.layers[0].name = "basement".to_string();
+        assert!(Board::from_file(terrains(), "test", file).is_err());
+    }
+
+    #[test]
+    fn test_rejects_other_versions() {
+        let mut data = encode_binary(&board().to_file()).unwrap();
+        data[BOARD_MAGIC.len()] += 1;
+        match decode_binary("test", &data) {
+            Err(BoardError::Version(_, 4)) => (),
+            _ => panic!("expected a version error"),
+        }
+
+        match decode_toml("test.toml", b"version = 0") {
+            Err(BoardError::Version(_, 0)) => (),
+            _ => panic!("expected a version error"),
+        }
+    }
+
+    #[test]
+    fn test_reads_version_2() {
+        let board = board();
+        let file = board.to_file();
+        let old = BoardFileV2 {
+            version: BOARD_VERSION_2,
+            width: file.width,
+            height: file.height,
+            terrain: file.terrain,
+            layers: file.layers,
+            features: file.features,
+        };
+
+        let mut data = header::write_header(BOARD_MAGIC, BOARD_VERSION_2);
+        data.extend_from_slice(&bincode::serialize(&old, bincode::Infinite).unwrap());
+        let text = toml::to_string(&old).unwrap();
+
+        for file in vec![decode_binary("test", &data).unwrap(), decode_toml("test.toml", text.as_bytes()).unwrap()] {
+            assert_eq!(file.version, BOARD_VERSION);
+            let loaded = Board::from_file(terrains(), "test", file).unwrap();
+            assert_eq!(loaded.to_ascii(&Legend::new()), board.to_ascii(&Legend::new()));
+            assert_eq!(loaded.features, board.features);
+            assert_eq!(loaded.explored().count(), 0);
+        }
+    }
+
+    #[test]
+    fn test_reads_version_1() {
+        let board = board();
+        let mut file = board.to_file();
+        assert_eq!(file.layers.len(), 1);
+        let old = BoardFileV1 {
+            version: BOARD_VERSION_1,
+            width: file.width,
+            height: file.height,
+            terrain: file.terrain,
+            rows: file.layers.remove(0).rows,
+            features: file.features,
+        };
+
+        let mut data = header::write_header(BOARD_MAGIC, BOARD_VERSION_1);
+        data.extend_from_slice(&bincode::serialize(&old, bincode::Infinite).unwrap());
+        let text = toml::to_string(&old).unwrap();
+
+        for file in vec![decode_binary("test", &data).unwrap(), decode_toml("test.toml", text.as_bytes()).unwrap()] {
+            assert_eq!(file.version, BOARD_VERSION);
+            let loaded = Board::from_file(terrains(), "test", file).unwrap();
+            assert_eq!(loaded.to_ascii(&Legend::new()), board.to_ascii(&Legend::new()));
+            assert_eq!(loaded.features, board.features);
+            assert!(loaded.is_layer_empty(Layer::Overlay));
+            assert_eq!(loaded.explored().count(), 0);
+        }
+    }
+
+    #[test]
+    fn test_rejects_unknown_terrain() {
+        let mut file = board().to_file();
+        file.terrain[1] = "Lava".to_string();
+        match Board::from_file(terrains(), "test", file) {
+            Err(BoardError::UnknownTerrain(_, ref name)) if name == "Lava" => (),
+            _ => panic!("expected an unknown terrain error"),
+        }
+    }
+
+    #[test]
+    fn test_rejects_ragged_rows() {
+        let mut file = board().to_file();
+        file.layers[0].rows[1].pop();
+        assert!(Board::from_file(terrains(), "test", file).is_err());
+    }
+}
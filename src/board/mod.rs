@@ -0,0 +1,486 @@
+use std::cmp;
+use std::collections::{HashMap, HashSet};
+use std::collections::{hash_map, hash_set};
+use std::fmt;
+use std::rc::Rc;
+
+use point::*;
+use terrain::*;
+
+mod ascii;
+pub mod chunk;
+mod file;
+pub mod tiled;
+
+pub use self::ascii::Legend;
+pub use self::chunk::{Chunk, ChunkDir, ChunkSource, CHUNK_SIZE};
+pub use self::file::{BoardError, BoardResult, BOARD_VERSION};
+
+/// The layers of a board, each holding its own terrain. Layers are autotiled
+/// separately and drawn in the order of `LAYERS`, so carpets can be laid
+/// over floors or roofs over walls.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Layer {
+    Ground,
+    Overlay,
+    Object,
+    Roof,
+}
+
+pub const LAYERS: [Layer; 4] = [Layer::Ground, Layer::Overlay, Layer::Object, Layer::Roof];
+
+impl Layer {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Layer::Ground  => "ground",
+            Layer::Overlay => "overlay",
+            Layer::Object  => "object",
+            Layer::Roof    => "roof",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Layer> {
+        match name {
+            "ground"  => Some(Layer::Ground),
+            "overlay" => Some(Layer::Overlay),
+            "object"  => Some(Layer::Object),
+            "roof"    => Some(Layer::Roof),
+            _         => None,
+        }
+    }
+
+    /// Gets the position of the layer in `LAYERS`.
+    pub fn index(&self) -> usize {
+        *self as usize
+    }
+}
+
+pub struct Board {
+    terrains: Rc<TerrainRegistry>,
+    dimensions: Point,
+
+    /// The ground terrain of chunks that haven't been loaded.
+    fill: TerrainId,
+
+    /// The loaded chunks, by chunk position.
+    chunks: HashMap<Point, Chunk>,
+    source: Option<Box<ChunkSource>>,
+
+    /// Chunks the source had nothing for, so it isn't asked again.
+    missing_chunks: HashSet<Point>,
+
+    features: HashMap<Point, Feature>,
+
+    /// Cells the player has seen, which are remembered once out of view.
+    /// Saved with the board.
+    explored: HashSet<Point>,
+
+    /// Cells whose terrain or feature changed since the last call to
+    /// `take_dirty`, so they can be redrawn without redrawing the board.
+    dirty: HashSet<Point>,
+}
+
+impl Board {
+    /// Makes a board with the ground filled with `fill` and the other
+    /// layers empty. Memory is only taken up by the chunks something is
+    /// placed in.
+    pub fn new(terrains: Rc<TerrainRegistry>, x: i32, y: i32, fill: TerrainId) -> Self {
+        Board {
+            terrains: terrains,
+            dimensions: Point::new(x, y),
+            fill: fill,
+            chunks: HashMap::new(),
+            source: None,
+            missing_chunks: HashSet::new(),
+            features: HashMap::new(),
+            explored: HashSet::new(),
+            dirty: HashSet::new(),
+        }
+    }
+
+    /// Makes a board whose chunks are taken from `source` as they are
+    /// needed, by `load_chunk` or `load_area`. Until then they hold `fill`.
+    /// Setting a cell in one loads it first.
+    pub fn with_source(terrains: Rc<TerrainRegistry>, x: i32, y: i32, fill: TerrainId,
+                       source: Box<ChunkSource>) -> Self {
+        let mut board = Board::new(terrains, x, y, fill);
+        board.source = Some(source);
+        board
+    }
+
+    /// Loads the chunk at the chunk position `chunk` from the source, if it
+    /// isn't loaded already. Its cells are marked dirty.
+    pub fn load_chunk(&mut self, chunk: &Point) -> BoardResult<()> {
+        if self.chunks.contains_key(chunk) || self.missing_chunks.contains(chunk) {
+            return Ok(());
+        }
+
+        let loaded = match self.source {
+            Some(ref mut source) => source.load_chunk(chunk)?,
+            None                 => None,
+        };
+
+        let loaded = match loaded {
+            Some(loaded) => loaded,
+            None         => {
+                self.missing_chunks.insert(*chunk);
+                return Ok(());
+            },
+        };
+
+        let origin = chunk::chunk_origin(chunk);
+        for &(local, ref feature) in loaded.features().iter() {
+            if self.in_bounds(&(origin + local)) {
+                self.features.insert(origin + local, feature.clone());
+            }
+        }
+        for pos in RectangleIter::new(origin, origin + (CHUNK_SIZE - 1, CHUNK_SIZE - 1)) {
+            if self.in_bounds(&pos) {
+                self.dirty.insert(pos);
+            }
+        }
+        self.chunks.insert(*chunk, loaded);
+        Ok(())
+    }
+
+    /// Loads every chunk touching the cells from `top_left` to
+    /// `bottom_right`, inclusive.
+    pub fn load_area(&mut self, top_left: &Point, bottom_right: &Point) -> BoardResult<()> {
+        let last = Point::new(self.width() - 1, self.height() - 1);
+        let from = chunk::chunk_of(&Point::new(cmp::max(top_left.x, 0), cmp::max(top_left.y, 0)));
+        let to = chunk::chunk_of(&Point::new(cmp::min(bottom_right.x, last.x), cmp::min(bottom_right.y, last.y)));
+
+        for y in from.y..(to.y + 1) {
+            for x in from.x..(to.x + 1) {
+                self.load_chunk(&Point::new(x, y))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Drops a chunk to free its memory, along with its features. The
+    /// source is asked for it again the next time it's loaded, so save it
+    /// first if it has changed.
+    pub fn unload_chunk(&mut self, chunk: &Point) -> Option<Chunk> {
+        self.missing_chunks.remove(chunk);
+        let removed = self.chunks.remove(chunk);
+
+        let origin = chunk::chunk_origin(chunk);
+        for pos in RectangleIter::new(origin, origin + (CHUNK_SIZE - 1, CHUNK_SIZE - 1)) {
+            if self.features.remove(&pos).is_some() || removed.is_some() {
+                if self.in_bounds(&pos) {
+                    self.dirty.insert(pos);
+                }
+            }
+        }
+        removed
+    }
+
+    /// Gets the number of chunks taking up memory.
+    pub fn loaded_chunks(&self) -> usize {
+        self.chunks.len()
+    }
+
+    pub fn in_bounds(&self, pt: &Point) -> bool {
+        *pt >= Point::new(0, 0) && *pt < self.dimensions
+    }
+
+    /// Sets the ground terrain at `pt`.
+    pub fn set(&mut self, pt: &Point, val: TerrainId) -> BoardResult<()> {
+        self.set_in(Layer::Ground, pt, val)
+    }
+
+    /// Gets the ground terrain at `pt`.
+    pub fn get(&self, pt: &Point) -> TerrainId {
+        self.get_in(Layer::Ground, pt)
+    }
+
+    /// Sets the terrain at `pt` on the given layer. On a board with a
+    /// source, the chunk is loaded first so the rest of it isn't lost, which
+    /// is the only way this can fail.
+    pub fn set_in(&mut self, layer: Layer, pt: &Point, val: TerrainId) -> BoardResult<()> {
+        if !self.in_bounds(pt) {
+            return Ok(());
+        }
+
+        self.load_chunk_of(pt)?;
+        if self.get_in(layer, pt) == val {
+            return Ok(());
+        }
+
+        let pos = chunk::chunk_of(pt);
+        let fill = self.fill;
+        let local = *pt - chunk::chunk_origin(&pos);
+        self.chunks.entry(pos)
+            .or_insert_with(|| Chunk::new(fill))
+            .set(layer, &local, val);
+        self.dirty.insert(*pt);
+        Ok(())
+    }
+
+    pub fn get_in(&self, layer: Layer, pt: &Point) -> TerrainId {
+        if !self.in_bounds(pt) {
+            return TERRAIN_NOTHING;
+        }
+
+        let pos = chunk::chunk_of(pt);
+        match self.chunks.get(&pos) {
+            Some(chunk) => chunk.get(layer, &(*pt - chunk::chunk_origin(&pos))),
+            None if layer == Layer::Ground => self.fill,
+            None => TERRAIN_NOTHING,
+        }
+    }
+
+    /// Gets the definition of the terrain at `pt` on the given layer.
+    pub fn terrain_in(&self, layer: Layer, pt: &Point) -> &TerrainDef {
+        self.terrains.get(self.get_in(layer, pt))
+    }
+
+    /// Checks if nothing has been placed on the layer.
+    pub fn is_layer_empty(&self, layer: Layer) -> bool {
+        let chunks_x = (self.width() + CHUNK_SIZE - 1) / CHUNK_SIZE;
+        let chunks_y = (self.height() + CHUNK_SIZE - 1) / CHUNK_SIZE;
+        let all_loaded = self.chunks.len() as i32 >= chunks_x * chunks_y;
+
+        if layer == Layer::Ground && self.fill != TERRAIN_NOTHING && !all_loaded {
+            return false;
+        }
+        self.chunks.values().all(|chunk| chunk.is_layer_empty(layer))
+    }
+
+    /// Gets one layer of the board, to autotile it on its own.
+    pub fn layer(&self, layer: Layer) -> LayerView {
+        LayerView {
+            board: self,
+            layer: layer,
+        }
+    }
+
+    /// Loads the chunk holding `pt` from the source, if there is one, so
+    /// changes to it aren't lost or overwritten when it's loaded later.
+    fn load_chunk_of(&mut self, pt: &Point) -> BoardResult<()> {
+        if self.source.is_some() {
+            self.load_chunk(&chunk::chunk_of(pt))?;
+        }
+        Ok(())
+    }
+
+    /// Places a feature at `pt`, replacing any feature already there.
+    pub fn set_feature(&mut self, pt: &Point, feature: Feature) -> BoardResult<()> {
+        if !self.in_bounds(pt) {
+            return Ok(());
+        }
+
+        self.load_chunk_of(pt)?;
+        self.features.insert(*pt, feature);
+        self.dirty.insert(*pt);
+        Ok(())
+    }
+
+    pub fn feature_at(&self, pt: &Point) -> Option<&Feature> {
+        self.features.get(pt)
+    }
+
+    pub fn remove_feature(&mut self, pt: &Point) -> BoardResult<Option<Feature>> {
+        if !self.in_bounds(pt) {
+            return Ok(None);
+        }
+
+        self.load_chunk_of(pt)?;
+        let removed = self.features.remove(pt);
+        if removed.is_some() {
+            self.dirty.insert(*pt);
+        }
+        Ok(removed)
+    }
+
+    /// Iterates over every feature on the board, in no particular order.
+    pub fn features(&self) -> hash_map::Iter<Point, Feature> {
+        self.features.iter()
+    }
+
+    /// Opens the door at `pt` if it's closed and closes it if it's open.
+    /// Returns false if there is no door there.
+    pub fn toggle_door(&mut self, pt: &Point) -> BoardResult<bool> {
+        if !self.in_bounds(pt) {
+            return Ok(false);
+        }
+
+        self.load_chunk_of(pt)?;
+        match self.features.get_mut(pt) {
+            Some(&mut Feature::Door(ref mut state)) => {
+                *state = match *state {
+                    DoorState::Closed => DoorState::Open,
+                    DoorState::Open   => DoorState::Closed,
+                };
+            },
+            _ => return Ok(false),
+        }
+
+        self.dirty.insert(*pt);
+        Ok(true)
+    }
+
+    /// Marks the cells in `visible`, as found by `fov::shadowcast`, as
+    /// explored.
+    pub fn explore(&mut self, visible: &HashSet<Point>) {
+        for pt in visible.iter() {
+            if self.in_bounds(pt) {
+                self.explored.insert(*pt);
+            }
+        }
+    }
+
+    pub fn is_explored(&self, pt: &Point) -> bool {
+        self.explored.contains(pt)
+    }
+
+    /// Iterates over every explored cell, in no particular order.
+    pub fn explored(&self) -> hash_set::Iter<Point> {
+        self.explored.iter()
+    }
+
+    /// Checks if any cell changed since the last call to `take_dirty`.
+    pub fn is_dirty(&self) -> bool {
+        !self.dirty.is_empty()
+    }
+
+    /// Gets the cells changed since the last call, top row first, and
+    /// forgets them.
+    pub fn take_dirty(&mut self) -> Vec<Point> {
+        let mut dirty: Vec<Point> = self.dirty.drain().collect();
+        dirty.sort_by_key(|pt| (pt.y, pt.x));
+        dirty
+    }
+
+    /// Gets the definition of the ground terrain at `pt`.
+    pub fn terrain_at(&self, pt: &Point) -> &TerrainDef {
+        self.terrains.get(self.get(pt))
+    }
+
+    pub fn terrains(&self) -> &Rc<TerrainRegistry> {
+        &self.terrains
+    }
+
+    pub fn width(&self) -> i32 {
+        self.dimensions.x
+    }
+
+    pub fn height(&self) -> i32 {
+        self.dimensions.y
+    }
+}
+
+/// Draws the board with terrain glyphs, one line per row, as
+/// `Board::parse_ascii` reads it.
+impl fmt::Display for Board {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_ascii(&Legend::new()))
+    }
+}
+
+/// One layer of a board.
+pub struct LayerView<'a> {
+    board: &'a Board,
+    layer: Layer,
+}
+
+impl<'a> LayerView<'a> {
+    pub fn get(&self, pt: &Point) -> TerrainId {
+        self.board.get_in(self.layer, pt)
+    }
+
+    pub fn in_bounds(&self, pt: &Point) -> bool {
+        self.board.in_bounds(pt)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn board() -> Board {
+        Board::new(Rc::new(TerrainRegistry::new()), 3, 2, TERRAIN_NOTHING)
+    }
+
+    #[test]
+    fn test_features() {
+        let mut board = board();
+        let pos = Point::new(2, 1);
+
+        board.set_feature(&pos, Feature::UpStair).unwrap();
+        assert_eq!(board.feature_at(&pos), Some(&Feature::UpStair));
+        assert_eq!(board.features().count(), 1);
+
+        assert_eq!(board.remove_feature(&pos).unwrap(), Some(Feature::UpStair));
+        assert_eq!(board.feature_at(&pos), None);
+        assert_eq!(board.remove_feature(&pos).unwrap(), None);
+    }
+
+    #[test]
+    fn test_features_off_the_board_are_ignored() {
+        let mut board = board();
+        board.set_feature(&Point::new(3, 0), Feature::DownStair).unwrap();
+        assert_eq!(board.features().count(), 0);
+    }
+
+    #[test]
+    fn test_toggle_door() {
+        let mut board = board();
+        let door = Point::new(0, 0);
+        let stair = Point::new(1, 0);
+        board.set_feature(&door, Feature::Door(DoorState::Closed)).unwrap();
+        board.set_feature(&stair, Feature::DownStair).unwrap();
+
+        assert!(board.toggle_door(&door).unwrap());
+        assert_eq!(board.feature_at(&door), Some(&Feature::Door(DoorState::Open)));
+        assert!(board.toggle_door(&door).unwrap());
+        assert_eq!(board.feature_at(&door), Some(&Feature::Door(DoorState::Closed)));
+
+        assert!(!board.toggle_door(&stair).unwrap());
+        assert!(!board.toggle_door(&Point::new(2, 1)).unwrap());
+    }
+
+    #[test]
+    fn test_dirty_cells() {
+        let mut terrains = TerrainRegistry::new();
+        let wall = terrains.add(TerrainDef {
+            name: "Wall".to_string(),
+            glyph: '#',
+            tile: None,
+            blocking: true,
+            group: None,
+        });
+        let mut board = Board::new(Rc::new(terrains), 3, 2, TERRAIN_NOTHING);
+        assert!(!board.is_dirty());
+
+        board.set(&Point::new(2, 1), wall).unwrap();
+        board.set_in(Layer::Roof, &Point::new(0, 1), wall).unwrap();
+        board.set_feature(&Point::new(1, 0), Feature::UpStair).unwrap();
+        // Setting a cell to what it already holds changes nothing.
+        board.set(&Point::new(0, 0), TERRAIN_NOTHING).unwrap();
+        // Neither does anything off the board.
+        board.set(&Point::new(5, 5), wall).unwrap();
+
+        assert!(board.is_dirty());
+        assert_eq!(board.take_dirty(), vec![Point::new(1, 0), Point::new(0, 1), Point::new(2, 1)]);
+        assert!(!board.is_dirty());
+        assert!(board.take_dirty().is_empty());
+
+        board.remove_feature(&Point::new(1, 0)).unwrap();
+        board.remove_feature(&Point::new(2, 0)).unwrap();
+        assert_eq!(board.take_dirty(), vec![Point::new(1, 0)]);
+    }
+
+    #[test]
+    fn test_explore() {
+        let mut board = board();
+        let visible: HashSet<Point> = vec![Point::new(0, 0), Point::new(1, 0), Point::new(3, 0)].into_iter().collect();
+        board.explore(&visible);
+
+        assert!(board.is_explored(&Point::new(1, 0)));
+        assert!(!board.is_explored(&Point::new(2, 0)));
+        // Cells off the board are never explored.
+        assert_eq!(board.explored().count(), 2);
+    }
+}
@@ -0,0 +1,280 @@
+//! Tiled's JSON map format.
+
+use serde_json::{self, Value};
+
+use super::{Layer, Map, Object, Properties, Tileset};
+
+#[derive(Serialize, Deserialize)]
+struct JsonMap {
+    #[serde(rename = "type", default = "map_type")]
+    kind: String,
+    #[serde(default = "orthogonal")]
+    orientation: String,
+    #[serde(default = "right_down")]
+    renderorder: String,
+    width: u32,
+    height: u32,
+    tilewidth: u32,
+    tileheight: u32,
+    #[serde(default)]
+    infinite: bool,
+    #[serde(default)]
+    nextobjectid: u32,
+    #[serde(default)]
+    tilesets: Vec<JsonTileset>,
+    #[serde(default)]
+    layers: Vec<JsonLayer>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct JsonTileset {
+    /// Left out of external tileset files.
+    #[serde(default)]
+    firstgid: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    source: Option<String>,
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    tilewidth: u32,
+    #[serde(default)]
+    tileheight: u32,
+    #[serde(default)]
+    tilecount: u32,
+    #[serde(default)]
+    columns: u32,
+    #[serde(default)]
+    tiles: Vec<JsonTile>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct JsonTile {
+    id: u32,
+    #[serde(default)]
+    properties: Vec<JsonProperty>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct JsonProperty {
+    name: String,
+    #[serde(rename = "type", default = "string_type")]
+    kind: String,
+    value: Value,
+}
+
+#[derive(Serialize, Deserialize)]
+struct JsonLayer {
+    #[serde(rename = "type")]
+    kind: String,
+    name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    width: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    height: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    data: Option<Vec<u32>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    objects: Option<Vec<JsonObject>>,
+    #[serde(default = "one")]
+    opacity: f32,
+    #[serde(default = "yes")]
+    visible: bool,
+    #[serde(default)]
+    x: i32,
+    #[serde(default)]
+    y: i32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct JsonObject {
+    #[serde(default)]
+    id: u32,
+    #[serde(default)]
+    name: String,
+    #[serde(rename = "type", default)]
+    kind: String,
+    /// Newer versions of Tiled write the type here instead.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    class: String,
+    x: f32,
+    y: f32,
+    #[serde(default)]
+    width: f32,
+    #[serde(default)]
+    height: f32,
+    #[serde(default)]
+    rotation: f32,
+    #[serde(default = "yes")]
+    visible: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    gid: Option<u32>,
+}
+
+fn map_type() -> String { "map".to_string() }
+fn orthogonal() -> String { "orthogonal".to_string() }
+fn right_down() -> String { "right-down".to_string() }
+fn string_type() -> String { "string".to_string() }
+fn one() -> f32 { 1.0 }
+fn yes() -> bool { true }
+
+pub fn parse_map(text: &str) -> Result<Map, String> {
+    let map: JsonMap = serde_json::from_str(text).map_err(|e| e.to_string())?;
+
+    if map.orientation != "orthogonal" {
+        return Err(format!("{} maps aren't supported", map.orientation));
+    }
+    if map.infinite {
+        return Err("infinite maps aren't supported".to_string());
+    }
+
+    let mut layers = Vec::new();
+    for layer in map.layers.into_iter() {
+        match layer.kind.as_str() {
+            "tilelayer" => layers.push(Layer::Tiles {
+                name: layer.name,
+                data: layer.data.unwrap_or(Vec::new()),
+            }),
+            "objectgroup" => layers.push(Layer::Objects {
+                name: layer.name,
+                objects: layer.objects.unwrap_or(Vec::new()).into_iter().map(to_object).collect(),
+            }),
+            // Image and group layers hold nothing a board can use.
+            _ => (),
+        }
+    }
+
+    Ok(Map {
+        width: map.width,
+        height: map.height,
+        tile_width: map.tilewidth,
+        tile_height: map.tileheight,
+        tilesets: map.tilesets.into_iter().map(to_tileset).collect(),
+        layers: layers,
+    })
+}
+
+/// Parses an external tileset file.
+pub fn parse_tileset(text: &str) -> Result<Tileset, String> {
+    let tileset: JsonTileset = serde_json::from_str(text).map_err(|e| e.to_string())?;
+    Ok(to_tileset(tileset))
+}
+
+fn to_tileset(tileset: JsonTileset) -> Tileset {
+    Tileset {
+        first_gid: tileset.firstgid,
+        name: tileset.name,
+        source: tileset.source,
+        tiles: tileset.tiles.into_iter().map(|tile| {
+            let properties = tile.properties.into_iter().map(|p| {
+                let value = match p.value {
+                    Value::String(s) => s,
+                    other            => other.to_string(),
+                };
+                (p.name, value)
+            }).collect();
+            (tile.id, properties)
+        }).collect(),
+    }
+}
+
+fn to_object(object: JsonObject) -> Object {
+    Object {
+        id: object.id,
+        name: object.name,
+        kind: if object.kind.is_empty() { object.class } else { object.kind },
+        x: object.x,
+        y: object.y,
+        width: object.width,
+        height: object.height,
+        gid: object.gid,
+    }
+}
+
+pub fn write_map(map: &Map) -> Result<String, String> {
+    let mut next_object_id = 1;
+
+    let layers: Vec<JsonLayer> = map.layers.iter().map(|layer| {
+        let (kind, name, data, objects) = match *layer {
+            Layer::Tiles { ref name, ref data } => ("tilelayer", name, Some(data.clone()), None),
+            Layer::Objects { ref name, ref objects } => {
+                let objects: Vec<JsonObject> = objects.iter().map(from_object).collect();
+                next_object_id += objects.len() as u32;
+                ("objectgroup", name, None, Some(objects))
+            },
+        };
+        let tile_layer = data.is_some();
+
+        JsonLayer {
+            kind: kind.to_string(),
+            name: name.clone(),
+            width: if tile_layer { Some(map.width) } else { None },
+            height: if tile_layer { Some(map.height) } else { None },
+            data: data,
+            objects: objects,
+            opacity: 1.0,
+            visible: true,
+            x: 0,
+            y: 0,
+        }
+    }).collect();
+
+    let tilesets: Vec<JsonTileset> = map.tilesets.iter().map(|tileset| {
+        JsonTileset {
+            firstgid: tileset.first_gid,
+            source: tileset.source.clone(),
+            name: tileset.name.clone(),
+            tilewidth: map.tile_width,
+            tileheight: map.tile_height,
+            tilecount: tileset.tiles.len() as u32,
+            columns: 0,
+            tiles: tileset.tiles.iter().map(|&(id, ref properties)| {
+                JsonTile {
+                    id: id,
+                    properties: from_properties(properties),
+                }
+            }).collect(),
+        }
+    }).collect();
+
+    let json = JsonMap {
+        kind: map_type(),
+        orientation: orthogonal(),
+        renderorder: right_down(),
+        width: map.width,
+        height: map.height,
+        tilewidth: map.tile_width,
+        tileheight: map.tile_height,
+        infinite: false,
+        nextobjectid: next_object_id,
+        tilesets: tilesets,
+        layers: layers,
+    };
+
+    serde_json::to_string_pretty(&json).map_err(|e| e.to_string())
+}
+
+fn from_properties(properties: &Properties) -> Vec<JsonProperty> {
+    properties.iter().map(|&(ref name, ref value)| {
+        JsonProperty {
+            name: name.clone(),
+            kind: string_type(),
+            value: Value::String(value.clone()),
+        }
+    }).collect()
+}
+
+fn from_object(object: &Object) -> JsonObject {
+    JsonObject {
+        id: object.id,
+        name: object.name.clone(),
+        kind: object.kind.clone(),
+        class: String::new(),
+        x: object.x,
+        y: object.y,
+        width: object.width,
+        height: object.height,
+        rotation: 0.0,
+        visible: true,
+        gid: object.gid,
+    }
+}
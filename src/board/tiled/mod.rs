@@ -0,0 +1,506 @@
+//! Importing and exporting maps made in the Tiled editor.
+//!
+//! Both the JSON (`.json`) and TMX (`.tmx`) formats are read and written,
+//! picked by file extension. Only orthogonal maps with uncompressed layer
+//! data are understood: save TMX maps with the CSV or XML layer format.
+//!
+//! Tiled tile ids are mapped to terrain through the custom properties of the
+//! tiles in each tileset. A `terrain` property names the terrain directly,
+//! and a `tile` property names a tile in `tiles.toml`, which is drawn by the
+//! first terrain using it.
+//!
+//! Tile layers named after a board layer (`ground`, `overlay`, `object`,
+//! `roof`, in any case) fill that layer, and any other tile layer fills the
+//! ground. Tile layers are applied in order, so cells left empty on a later
+//! layer keep the terrain of earlier ones.
+//!
+//! Objects on object layers whose type is a feature name (`door`,
+//! `open_door`, `up_stair`, `down_stair`) become features, and objects of
+//! type `sprite` are returned as sprites named after the object. Other
+//! objects are ignored.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::rc::Rc;
+
+use board::{Board, BoardError, BoardResult, LAYERS};
+use board::Layer as BoardLayer;
+use point::Point;
+use terrain::{Feature, TerrainId, TerrainRegistry, TERRAIN_NOTHING};
+
+mod json;
+mod tmx;
+
+/// Tiled stores flip and rotation flags in the high bits of tile ids.
+const GID_MASK: u32 = 0x0FFF_FFFF;
+
+/// A sprite placed on an object layer.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SpritePlacement {
+    /// Name of the sprite in `sprites.toml`.
+    pub name: String,
+    pub pos: Point,
+}
+
+/// A board read from a Tiled map, along with the sprites placed on it.
+pub struct TiledBoard {
+    pub board: Board,
+    pub sprites: Vec<SpritePlacement>,
+}
+
+/// The parts of a Tiled map that are read and written, shared by both file
+/// formats.
+#[derive(Debug)]
+struct Map {
+    width: u32,
+    height: u32,
+    tile_width: u32,
+    tile_height: u32,
+    tilesets: Vec<Tileset>,
+    layers: Vec<Layer>,
+}
+
+#[derive(Debug)]
+struct Tileset {
+    first_gid: u32,
+    name: String,
+
+    /// Path of the external tileset file, relative to the map, if the
+    /// tileset isn't embedded. Its tiles are filled in once it is loaded.
+    source: Option<String>,
+
+    /// Custom properties of each tile, by id within the tileset.
+    tiles: Vec<(u32, Properties)>,
+}
+
+type Properties = Vec<(String, String)>;
+
+#[derive(Debug)]
+enum Layer {
+    /// Tile ids row by row, with 0 for an empty cell.
+    Tiles { name: String, data: Vec<u32> },
+    Objects { name: String, objects: Vec<Object> },
+}
+
+#[derive(Debug, Default)]
+struct Object {
+    id: u32,
+    name: String,
+    kind: String,
+
+    /// Position and size in pixels. Tile objects are anchored at their
+    /// bottom left corner, other objects at their top left.
+    x: f32,
+    y: f32,
+    width: f32,
+    height: f32,
+    gid: Option<u32>,
+}
+
+fn property<'a>(properties: &'a Properties, key: &str) -> Option<&'a str> {
+    properties.iter().find(|&&(ref k, _)| k == key).map(|&(_, ref v)| v.as_str())
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    Json,
+    Tmx,
+}
+
+fn format_of(path: &str) -> BoardResult<Format> {
+    match Path::new(path).extension().and_then(|ext| ext.to_str()) {
+        Some("json") => Ok(Format::Json),
+        Some("tmx")  => Ok(Format::Tmx),
+        _            => Err(BoardError::Format(path.to_string(), "expected a .json or .tmx Tiled map".to_string())),
+    }
+}
+
+fn read_file(path: &str) -> BoardResult<String> {
+    let mut text = String::new();
+    File::open(path)
+        .and_then(|mut f| f.read_to_string(&mut text))
+        .map_err(|e| BoardError::Io(path.to_string(), e))?;
+    Ok(text)
+}
+
+/// Reads a Tiled map, looking its terrain up in `terrains`.
+pub fn import(terrains: Rc<TerrainRegistry>, path: &str) -> BoardResult<TiledBoard> {
+    let format = format_of(path)?;
+    let text = read_file(path)?;
+
+    let mut map = match format {
+        Format::Json => json::parse_map(&text),
+        Format::Tmx  => tmx::parse_map(&text),
+    }.map_err(|msg| BoardError::Format(path.to_string(), msg))?;
+
+    // Load external tilesets from next to the map.
+    let folder = Path::new(path).parent().unwrap_or(Path::new(""));
+    for tileset in map.tilesets.iter_mut() {
+        let source = match tileset.source {
+            Some(ref source) => folder.join(source).display().to_string(),
+            None             => continue,
+        };
+        let text = read_file(&source)?;
+        let loaded = match format {
+            Format::Json => json::parse_tileset(&text),
+            Format::Tmx  => tmx::parse_tileset(&text),
+        }.map_err(|msg| BoardError::Format(source.clone(), msg))?;
+
+        tileset.name = loaded.name;
+        tileset.tiles = loaded.tiles;
+    }
+
+    to_board(terrains, path, &map)
+}
+
+/// Writes a board and the sprites on it as a Tiled map, with cells of
+/// `tile_size` pixels.
+///
+/// The map gets one tileset with a tile for each terrain used on the board,
+/// carrying `terrain` and `tile` properties so it can be imported again, and
+/// a tile layer for the ground and each other board layer that isn't empty.
+pub fn export(board: &Board, sprites: &[SpritePlacement], tile_size: (u32, u32), path: &str) -> BoardResult<()> {
+    let format = format_of(path)?;
+    let map = from_board(board, sprites, tile_size);
+
+    let text = match format {
+        Format::Json => json::write_map(&map),
+        Format::Tmx  => Ok(tmx::write_map(&map)),
+    }.map_err(|msg| BoardError::Format(path.to_string(), msg))?;
+
+    File::create(path)
+        .and_then(|mut f| f.write_all(text.as_bytes()))
+        .map_err(|e| BoardError::Io(path.to_string(), e))
+}
+
+/// Maps every tile id with a `terrain` or `tile` property to its terrain.
+fn terrain_ids(terrains: &TerrainRegistry, path: &str, tilesets: &[Tileset]) -> BoardResult<HashMap<u32, TerrainId>> {
+    let mut ids = HashMap::new();
+
+    for tileset in tilesets.iter() {
+        for &(id, ref properties) in tileset.tiles.iter() {
+            let terrain = if let Some(name) = property(properties, "terrain") {
+                match terrains.find(name) {
+                    Some(terrain) => terrain,
+                    None          => return Err(BoardError::UnknownTerrain(path.to_string(), name.to_string())),
+                }
+            } else if let Some(tile) = property(properties, "tile") {
+                match terrains.find_by_tile(tile) {
+                    Some(terrain) => terrain,
+                    None          => return Err(BoardError::UnknownTerrain(path.to_string(), tile.to_string())),
+                }
+            } else {
+                continue;
+            };
+
+            let gid = match tileset.first_gid.checked_add(id) {
+                Some(gid) => gid,
+                None      => return Err(BoardError::Format(path.to_string(),
+                                                           format!("tile {} of tileset \"{}\" is out of range",
+                                                                   id, tileset.name))),
+            };
+            ids.insert(gid, terrain);
+        }
+    }
+
+    Ok(ids)
+}
+
+/// Gets the cell an object is over, going by its center.
+fn object_cell(object: &Object, tile_size: (u32, u32)) -> Point {
+    let center_y = if object.gid.is_some() {
+        object.y - object.height / 2.0
+    } else {
+        object.y + object.height / 2.0
+    };
+    let center_x = object.x + object.width / 2.0;
+
+    Point::new((center_x / tile_size.0 as f32).floor() as i32,
+               (center_y / tile_size.1 as f32).floor() as i32)
+}
+
+fn to_board(terrains: Rc<TerrainRegistry>, path: &str, map: &Map) -> BoardResult<TiledBoard> {
+    let invalid = |msg: String| BoardError::Format(path.to_string(), msg);
+
+    if map.tile_width == 0 || map.tile_height == 0 {
+        return Err(invalid("tile size is zero".to_string()));
+    }
+    if map.width == 0 || map.height == 0 {
+        return Err(invalid("map size is zero".to_string()));
+    }
+    let cells = match map.width.checked_mul(map.height) {
+        Some(cells) if map.width <= ::std::i32::MAX as u32 && map.height <= ::std::i32::MAX as u32 => cells,
+        _ => return Err(invalid(format!("map size {}x{} is too large", map.width, map.height))),
+    };
+
+    let ids = terrain_ids(&terrains, path, &map.tilesets)?;
+    let mut board = Board::new(terrains, map.width as i32, map.height as i32, TERRAIN_NOTHING);
+    let mut sprites = Vec::new();
+
+    for layer in map.layers.iter() {
+        match *layer {
+            Layer::Tiles { ref name, ref data } => {
+                if data.len() != cells as usize {
+                    return Err(invalid(format!("layer \"{}\" has {} cells, expected {}x{}",
+                                               name, data.len(), map.width, map.height)));
+                }
+                let target = BoardLayer::from_name(&name.to_lowercase()).unwrap_or(BoardLayer::Ground);
+
+                for (i, gid) in data.iter().enumerate() {
+                    let gid = gid & GID_MASK;
+                    if gid == 0 {
+                        continue;
+                    }
+
+                    let terrain = match ids.get(&gid) {
+                        Some(&terrain) => terrain,
+                        None           => return Err(BoardError::UnknownTile(path.to_string(), gid)),
+                    };
+                    let pos = Point::new((i as u32 % map.width) as i32, (i as u32 / map.width) as i32);
+                    board.set_in(target, &pos, terrain)?;
+                }
+            },
+            Layer::Objects { ref objects, .. } => {
+                for object in objects.iter() {
+                    let pos = object_cell(object, (map.tile_width, map.tile_height));
+
+                    if let Some(feature) = Feature::from_name(&object.kind) {
+                        if !board.in_bounds(&pos) {
+                            return Err(invalid(format!("object {} is off the map", object.id)));
+                        }
+                        board.set_feature(&pos, feature)?;
+                    } else if object.kind == "sprite" {
+                        sprites.push(SpritePlacement {
+                            name: object.name.clone(),
+                            pos: pos,
+                        });
+                    }
+                }
+            },
+        }
+    }
+
+    Ok(TiledBoard {
+        board: board,
+        sprites: sprites,
+    })
+}
+
+fn from_board(board: &Board, sprites: &[SpritePlacement], tile_size: (u32, u32)) -> Map {
+    // Tile ids start at 1, as 0 is an empty cell.
+    let mut gids: HashMap<TerrainId, u32> = HashMap::new();
+    let mut tiles = Vec::new();
+    let mut layers = Vec::new();
+
+    for &layer in LAYERS.iter() {
+        if layer != BoardLayer::Ground && board.is_layer_empty(layer) {
+            continue;
+        }
+
+        let mut data = Vec::new();
+        for y in 0..board.height() {
+            for x in 0..board.width() {
+                let id = board.get_in(layer, &Point::new(x, y));
+                if id == TERRAIN_NOTHING {
+                    data.push(0);
+                    continue;
+                }
+
+                let gid = *gids.entry(id).or_insert_with(|| {
+                    let def = board.terrains().get(id);
+                    let mut properties = vec![("terrain".to_string(), def.name.clone())];
+                    if let Some(ref tile) = def.tile {
+                        properties.push(("tile".to_string(), tile.clone()));
+                    }
+                    let tile_id = tiles.len() as u32;
+                    tiles.push((tile_id, properties));
+                    tile_id + 1
+                });
+                data.push(gid);
+            }
+        }
+
+        layers.push(Layer::Tiles { name: layer.name().to_string(), data: data });
+    }
+
+    let (tw, th) = (tile_size.0 as f32, tile_size.1 as f32);
+    let mut objects = Vec::new();
+
+    let mut features: Vec<(&Point, &Feature)> = board.features().collect();
+    features.sort_by_key(|&(pos, _)| (pos.y, pos.x));
+
+    for (pos, feature) in features {
+        objects.push(Object {
+            kind: feature.name().to_string(),
+            x: pos.x as f32 * tw,
+            y: pos.y as f32 * th,
+            width: tw,
+            height: th,
+            .. Default::default()
+        });
+    }
+
+    for sprite in sprites.iter() {
+        objects.push(Object {
+            name: sprite.name.clone(),
+            kind: "sprite".to_string(),
+            x: sprite.pos.x as f32 * tw,
+            y: sprite.pos.y as f32 * th,
+            width: tw,
+            height: th,
+            .. Default::default()
+        });
+    }
+
+    for (i, object) in objects.iter_mut().enumerate() {
+        object.id = i as u32 + 1;
+    }
+    layers.push(Layer::Objects { name: "objects".to_string(), objects: objects });
+
+    Map {
+        width: board.width() as u32,
+        height: board.height() as u32,
+        tile_width: tile_size.0,
+        tile_height: tile_size.1,
+        tilesets: vec![Tileset {
+            first_gid: 1,
+            name: "terrain".to_string(),
+            source: None,
+            tiles: tiles,
+        }],
+        layers: layers,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use terrain::DoorState;
+    use testing::terrains;
+
+    fn board() -> Board {
+        let terrains = terrains();
+        let floor = terrains.find("Floor").unwrap();
+        let mut board = Board::new(terrains.clone(), 3, 2, terrains.find("Wall").unwrap());
+        board.set(&Point::new(1, 1), floor).unwrap();
+        board.set(&Point::new(2, 0), TERRAIN_NOTHING).unwrap();
+        board.set_in(BoardLayer::Roof, &Point::new(0, 0), floor).unwrap();
+        board.set_feature(&Point::new(1, 1), Feature::UpStair).unwrap();
+        board
+    }
+
+    fn sprites() -> Vec<SpritePlacement> {
+        vec![SpritePlacement { name: "Berry".to_string(), pos: Point::new(0, 1) }]
+    }
+
+    fn assert_same(a: &Board, b: &Board) {
+        assert_eq!((a.width(), a.height()), (b.width(), b.height()));
+        for y in 0..a.height() {
+            for x in 0..a.width() {
+                let pos = Point::new(x, y);
+                for &layer in LAYERS.iter() {
+                    assert_eq!(a.terrain_in(layer, &pos).name, b.terrain_in(layer, &pos).name);
+                }
+            }
+        }
+        assert_eq!(a.features, b.features);
+    }
+
+    fn roundtrip(format: Format) {
+        let map = from_board(&board(), &sprites(), (48, 48));
+        let parsed = match format {
+            Format::Json => json::parse_map(&json::write_map(&map).unwrap()).unwrap(),
+            Format::Tmx  => tmx::parse_map(&tmx::write_map(&map)).unwrap(),
+        };
+
+        let imported = to_board(terrains(), "test", &parsed).unwrap();
+        assert_same(&board(), &imported.board);
+        assert_eq!(imported.sprites, sprites());
+    }
+
+    #[test]
+    fn test_json_roundtrip() {
+        roundtrip(Format::Json);
+    }
+
+    #[test]
+    fn test_tmx_roundtrip() {
+        roundtrip(Format::Tmx);
+    }
+
+    #[test]
+    fn test_import_maps_tile_names() {
+        let map = tmx::parse_map(r#"<?xml version="1.0" encoding="UTF-8"?>
+<map version="1.2" orientation="orthogonal" width="2" height="1" tilewidth="32" tileheight="32">
+ <tileset firstgid="5" name="dungeon" tilewidth="32" tileheight="32" tilecount="4" columns="2">
+  <image source="dungeon.png" width="64" height="64"/>
+  <tile id="1">
+   <properties>
+    <property name="tile" value="Water"/>
+   </properties>
+  </tile>
+ </tileset>
+ <layer name="ground" width="2" height="1">
+  <data encoding="csv">
+6,2147483654
+</data>
+ </layer>
+ <layer name="Roof" width="2" height="1">
+  <data encoding="csv">
+0,6
+</data>
+ </layer>
+ <objectgroup name="things">
+  <object id="1" type="door" x="32" y="0" width="32" height="32"/>
+  <object id="2" type="torch" x="0" y="0"/>
+ </objectgroup>
+</map>"#).unwrap();
+
+        let imported = to_board(terrains(), "test", &map).unwrap();
+        let board = imported.board;
+
+        // The second cell is the same tile flipped horizontally.
+        assert_eq!(board.terrain_at(&Point::new(0, 0)).name, "Floor");
+        assert_eq!(board.terrain_at(&Point::new(1, 0)).name, "Floor");
+        assert_eq!(board.get_in(BoardLayer::Roof, &Point::new(0, 0)), TERRAIN_NOTHING);
+        assert_eq!(board.terrain_in(BoardLayer::Roof, &Point::new(1, 0)).name, "Floor");
+        assert_eq!(board.feature_at(&Point::new(1, 0)), Some(&Feature::Door(DoorState::Closed)));
+        assert!(imported.sprites.is_empty());
+    }
+
+    #[test]
+    fn test_import_rejects_unmapped_tiles() {
+        let map = json::parse_map(r#"{
+            "width": 1, "height": 1, "tilewidth": 16, "tileheight": 16,
+            "tilesets": [{ "firstgid": 1, "name": "empty", "tiles": [] }],
+            "layers": [{ "type": "tilelayer", "name": "ground", "width": 1, "height": 1, "data": [3] }]
+        }"#).unwrap();
+
+        match to_board(terrains(), "test", &map) {
+            Err(BoardError::UnknownTile(_, 3)) => (),
+            _ => panic!("expected an unmapped tile error"),
+        }
+    }
+
+    #[test]
+    fn test_import_rejects_bad_sizes() {
+        for &(width, height) in [(0, 2), (3, 0), (0x10000, 0x10000)].iter() {
+            let mut map = from_board(&board(), &[], (48, 48));
+            map.width = width;
+            map.height = height;
+            match to_board(terrains(), "test", &map) {
+                Err(BoardError::Format(..)) => (),
+                _ => panic!("expected a format error for a {}x{} map", width, height),
+            }
+        }
+
+        let mut map = from_board(&board(), &[], (48, 48));
+        map.tilesets[0].first_gid = ::std::u32::MAX;
+        match to_board(terrains(), "test", &map) {
+            Err(BoardError::Format(..)) => (),
+            _ => panic!("expected a format error for an out of range tile id"),
+        }
+    }
+}
@@ -0,0 +1,286 @@
+//! Tiled's XML map format, TMX, and its external tileset files, TSX.
+
+use std::cmp;
+use std::str::FromStr;
+
+use xml::attribute::OwnedAttribute;
+use xml::reader::{EventReader, XmlEvent};
+
+use super::{Layer, Map, Object, Properties, Tileset};
+
+/// The elements being read, so properties and `<tile>` elements can be
+/// told apart by where they appear.
+#[derive(Default)]
+struct Parser {
+    map: Option<Map>,
+    tilesets: Vec<Tileset>,
+    layers: Vec<Layer>,
+
+    tileset: Option<Tileset>,
+    tile: Option<(u32, Properties)>,
+    layer: Option<(String, Vec<u32>)>,
+    csv: bool,
+    in_data: bool,
+    objects: Option<(String, Vec<Object>)>,
+    object: Option<Object>,
+}
+
+fn attr<'a>(attributes: &'a [OwnedAttribute], key: &str) -> Option<&'a str> {
+    attributes.iter().find(|a| a.name.local_name == key).map(|a| a.value.as_str())
+}
+
+fn parse_attr<T: FromStr>(attributes: &[OwnedAttribute], element: &str, key: &str) -> Result<Option<T>, String> {
+    match attr(attributes, key) {
+        Some(value) => value.parse().map(Some).map_err(|_| format!("invalid {} in <{}>: \"{}\"", key, element, value)),
+        None        => Ok(None),
+    }
+}
+
+fn expect_attr<T: FromStr>(attributes: &[OwnedAttribute], element: &str, key: &str) -> Result<T, String> {
+    match parse_attr(attributes, element, key)? {
+        Some(value) => Ok(value),
+        None        => Err(format!("<{}> has no {}", element, key)),
+    }
+}
+
+impl Parser {
+    fn start(&mut self, element: &str, attributes: &[OwnedAttribute]) -> Result<(), String> {
+        match element {
+            "map" => {
+                if let Some(orientation) = attr(attributes, "orientation") {
+                    if orientation != "orthogonal" {
+                        return Err(format!("{} maps aren't supported", orientation));
+                    }
+                }
+                if attr(attributes, "infinite") == Some("1") {
+                    return Err("infinite maps aren't supported".to_string());
+                }
+
+                self.map = Some(Map {
+                    width: expect_attr(attributes, element, "width")?,
+                    height: expect_attr(attributes, element, "height")?,
+                    tile_width: expect_attr(attributes, element, "tilewidth")?,
+                    tile_height: expect_attr(attributes, element, "tileheight")?,
+                    tilesets: Vec::new(),
+                    layers: Vec::new(),
+                });
+            },
+            "tileset" => {
+                self.tileset = Some(Tileset {
+                    // Left out of external tileset files.
+                    first_gid: parse_attr(attributes, element, "firstgid")?.unwrap_or(0),
+                    name: attr(attributes, "name").unwrap_or("").to_string(),
+                    source: attr(attributes, "source").map(|s| s.to_string()),
+                    tiles: Vec::new(),
+                });
+            },
+            "tile" if self.in_data => {
+                if let Some((_, ref mut data)) = self.layer {
+                    data.push(parse_attr(attributes, element, "gid")?.unwrap_or(0));
+                }
+            },
+            "tile" if self.tileset.is_some() => {
+                self.tile = Some((expect_attr(attributes, element, "id")?, Vec::new()));
+            },
+            "property" => {
+                if let Some((_, ref mut properties)) = self.tile {
+                    let name = expect_attr(attributes, element, "name")?;
+                    let value = attr(attributes, "value").unwrap_or("").to_string();
+                    properties.push((name, value));
+                }
+            },
+            "layer" => {
+                self.layer = Some((attr(attributes, "name").unwrap_or("").to_string(), Vec::new()));
+            },
+            "data" => {
+                if attr(attributes, "compression").is_some() {
+                    return Err("compressed layer data isn't supported; save the map with the CSV layer format".to_string());
+                }
+                self.csv = match attr(attributes, "encoding") {
+                    Some("csv") => true,
+                    None        => false,
+                    Some(other) => return Err(format!("{} layer data isn't supported; save the map with the CSV layer format", other)),
+                };
+                self.in_data = true;
+            },
+            // Tiles can hold collision shapes in object groups of their own.
+            "objectgroup" if self.tileset.is_none() => {
+                self.objects = Some((attr(attributes, "name").unwrap_or("").to_string(), Vec::new()));
+            },
+            "object" => {
+                // Newer versions of Tiled write the type as the class.
+                let kind = attr(attributes, "type").or(attr(attributes, "class")).unwrap_or("");
+
+                self.object = Some(Object {
+                    id: parse_attr(attributes, element, "id")?.unwrap_or(0),
+                    name: attr(attributes, "name").unwrap_or("").to_string(),
+                    kind: kind.to_string(),
+                    x: expect_attr(attributes, element, "x")?,
+                    y: expect_attr(attributes, element, "y")?,
+                    width: parse_attr(attributes, element, "width")?.unwrap_or(0.0),
+                    height: parse_attr(attributes, element, "height")?.unwrap_or(0.0),
+                    gid: parse_attr(attributes, element, "gid")?,
+                });
+            },
+            _ => (),
+        }
+
+        Ok(())
+    }
+
+    fn text(&mut self, text: &str) -> Result<(), String> {
+        if !(self.in_data && self.csv) {
+            return Ok(());
+        }
+
+        if let Some((_, ref mut data)) = self.layer {
+            for gid in text.split(',').map(|s| s.trim()).filter(|s| !s.is_empty()) {
+                data.push(gid.parse().map_err(|_| format!("invalid tile id \"{}\" in layer data", gid))?);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn end(&mut self, element: &str) {
+        match element {
+            "tileset" => {
+                if let Some(tileset) = self.tileset.take() {
+                    self.tilesets.push(tileset);
+                }
+            },
+            "tile" if !self.in_data => {
+                if let (Some(tile), Some(tileset)) = (self.tile.take(), self.tileset.as_mut()) {
+                    tileset.tiles.push(tile);
+                }
+            },
+            "data" => self.in_data = false,
+            "layer" => {
+                if let Some((name, data)) = self.layer.take() {
+                    self.layers.push(Layer::Tiles { name: name, data: data });
+                }
+            },
+            "object" => {
+                if let (Some(object), Some(&mut (_, ref mut objects))) = (self.object.take(), self.objects.as_mut()) {
+                    objects.push(object);
+                }
+            },
+            "objectgroup" => {
+                if let Some((name, objects)) = self.objects.take() {
+                    self.layers.push(Layer::Objects { name: name, objects: objects });
+                }
+            },
+            _ => (),
+        }
+    }
+}
+
+fn parse(text: &str) -> Result<Parser, String> {
+    let mut parser = Parser::default();
+
+    for event in EventReader::from_str(text) {
+        match event.map_err(|e| e.to_string())? {
+            XmlEvent::StartElement { name, attributes, .. } => parser.start(&name.local_name, &attributes)?,
+            XmlEvent::EndElement { name } => parser.end(&name.local_name),
+            XmlEvent::Characters(text) => parser.text(&text)?,
+            _ => (),
+        }
+    }
+
+    Ok(parser)
+}
+
+pub fn parse_map(text: &str) -> Result<Map, String> {
+    let parser = parse(text)?;
+
+    match parser.map {
+        Some(mut map) => {
+            map.tilesets = parser.tilesets;
+            map.layers = parser.layers;
+            Ok(map)
+        },
+        None => Err("no <map> element".to_string()),
+    }
+}
+
+/// Parses an external tileset file.
+pub fn parse_tileset(text: &str) -> Result<Tileset, String> {
+    let mut parser = parse(text)?;
+
+    match parser.tilesets.pop() {
+        Some(tileset) => Ok(tileset),
+        None          => Err("no <tileset> element".to_string()),
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+pub fn write_map(map: &Map) -> String {
+    let object_count: usize = map.layers.iter().map(|layer| match *layer {
+        Layer::Objects { ref objects, .. } => objects.len(),
+        Layer::Tiles { .. }                => 0,
+    }).sum();
+
+    let mut out = String::new();
+    out.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    out.push_str(&format!("<map version=\"1.2\" orientation=\"orthogonal\" renderorder=\"right-down\" \
+                           width=\"{}\" height=\"{}\" tilewidth=\"{}\" tileheight=\"{}\" infinite=\"0\" nextobjectid=\"{}\">\n",
+                          map.width, map.height, map.tile_width, map.tile_height, object_count + 1));
+
+    for tileset in map.tilesets.iter() {
+        if let Some(ref source) = tileset.source {
+            out.push_str(&format!(" <tileset firstgid=\"{}\" source=\"{}\"/>\n", tileset.first_gid, escape(source)));
+            continue;
+        }
+
+        out.push_str(&format!(" <tileset firstgid=\"{}\" name=\"{}\" tilewidth=\"{}\" tileheight=\"{}\" tilecount=\"{}\" columns=\"0\">\n",
+                              tileset.first_gid, escape(&tileset.name), map.tile_width, map.tile_height, tileset.tiles.len()));
+        for &(id, ref properties) in tileset.tiles.iter() {
+            out.push_str(&format!("  <tile id=\"{}\">\n", id));
+            out.push_str("   <properties>\n");
+            for &(ref name, ref value) in properties.iter() {
+                out.push_str(&format!("    <property name=\"{}\" value=\"{}\"/>\n", escape(name), escape(value)));
+            }
+            out.push_str("   </properties>\n");
+            out.push_str("  </tile>\n");
+        }
+        out.push_str(" </tileset>\n");
+    }
+
+    for layer in map.layers.iter() {
+        match *layer {
+            Layer::Tiles { ref name, ref data } => {
+                out.push_str(&format!(" <layer name=\"{}\" width=\"{}\" height=\"{}\">\n", escape(name), map.width, map.height));
+                out.push_str("  <data encoding=\"csv\">\n");
+                let rows: Vec<String> = data.chunks(cmp::max(map.width, 1) as usize).map(|row| {
+                    row.iter().map(|gid| gid.to_string()).collect::<Vec<_>>().join(",")
+                }).collect();
+                out.push_str(&rows.join(",\n"));
+                out.push_str("\n</data>\n");
+                out.push_str(" </layer>\n");
+            },
+            Layer::Objects { ref name, ref objects } => {
+                out.push_str(&format!(" <objectgroup name=\"{}\">\n", escape(name)));
+                for object in objects.iter() {
+                    out.push_str(&format!("  <object id=\"{}\" name=\"{}\" type=\"{}\" x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\"",
+                                          object.id, escape(&object.name), escape(&object.kind),
+                                          object.x, object.y, object.width, object.height));
+                    if let Some(gid) = object.gid {
+                        out.push_str(&format!(" gid=\"{}\"", gid));
+                    }
+                    out.push_str("/>\n");
+                }
+                out.push_str(" </objectgroup>\n");
+            },
+        }
+    }
+
+    out.push_str("</map>\n");
+    out
+}
@@ -0,0 +1,190 @@
+//! Field of view, the cells that can be seen from a point on a board.
+
+use std::collections::HashSet;
+
+use board::{Board, Layer};
+use point::Point;
+use terrain::{DoorState, Feature, TERRAIN_NOTHING};
+
+/// Ways of turning the first octant, between north and north-east, onto
+/// each of the eight octants around the origin: the cell at `(dx, dy)` in
+/// the first octant is `(dx * xx + dy * xy, dx * yx + dy * yy)` from it.
+const OCTANTS: [(i32, i32, i32, i32); 8] = [
+    (1, 0, 0, 1),
+    (0, 1, 1, 0),
+    (0, -1, 1, 0),
+    (-1, 0, 0, 1),
+    (-1, 0, 0, -1),
+    (0, -1, -1, 0),
+    (0, 1, -1, 0),
+    (1, 0, 0, -1),
+];
+
+/// Checks if a cell can't be seen through: it's off the board, it or its
+/// object layer has blocking terrain, or it has a closed door.
+pub fn blocks_sight(board: &Board, pos: &Point) -> bool {
+    if !board.in_bounds(pos) || board.terrain_at(pos).blocking {
+        return true;
+    }
+
+    let object = board.get_in(Layer::Object, pos);
+    if object != TERRAIN_NOTHING && board.terrains().get(object).blocking {
+        return true;
+    }
+
+    match board.feature_at(pos) {
+        Some(&Feature::Door(DoorState::Closed)) => true,
+        _                                       => false,
+    }
+}
+
+/// Gets the cells that can be seen from `origin` within `radius`, using
+/// recursive shadowcasting. Cells that block sight are seen but hide what is
+/// behind them, so the walls of a room are visible from inside it. The cells
+/// within the radius are the same as those of a `CircleIter`.
+pub fn shadowcast(board: &Board, origin: &Point, radius: i32) -> HashSet<Point> {
+    let mut visible = HashSet::new();
+    if board.in_bounds(origin) {
+        visible.insert(*origin);
+    }
+
+    for octant in OCTANTS.iter() {
+        cast_light(board, origin, radius, 1, 1.0, 0.0, octant, &mut visible);
+    }
+
+    visible
+}
+
+/// Scans the rows of one octant outwards from `row`, between the slopes
+/// `start` and `end`, and scans again past each run of cells that blocks
+/// sight with the slopes narrowed to what can be seen around it.
+fn cast_light(board: &Board, origin: &Point, radius: i32, row: i32, start: f32, end: f32,
+              octant: &(i32, i32, i32, i32), visible: &mut HashSet<Point>) {
+    if start < end {
+        return;
+    }
+
+    let &(xx, xy, yx, yy) = octant;
+    let mut start = start;
+
+    for j in row..(radius + 1) {
+        let dy = -j;
+        let mut blocked = false;
+        let mut next_start = start;
+
+        for dx in -j..1 {
+            let left_slope = (dx as f32 - 0.5) / (dy as f32 + 0.5);
+            let right_slope = (dx as f32 + 0.5) / (dy as f32 - 0.5);
+            if start < right_slope {
+                continue;
+            } else if end > left_slope {
+                break;
+            }
+
+            let pos = Point::new(origin.x + dx * xx + dy * xy, origin.y + dx * yx + dy * yy);
+            if dx * dx + dy * dy < radius * radius && board.in_bounds(&pos) {
+                visible.insert(pos);
+            }
+
+            let opaque = blocks_sight(board, &pos);
+            if blocked {
+                if opaque {
+                    next_start = right_slope;
+                } else {
+                    blocked = false;
+                    start = next_start;
+                }
+            } else if opaque && j < radius {
+                blocked = true;
+                cast_light(board, origin, radius, j + 1, start, left_slope, octant, visible);
+                next_start = right_slope;
+            }
+        }
+
+        if blocked {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use board::Legend;
+    use point::CircleIter;
+    use testing::terrains;
+
+    /// Draws the visible cells of `board` as `*`, the rest as `?`.
+    fn draw(board: &Board, visible: &HashSet<Point>) -> String {
+        let mut out = String::new();
+        for y in 0..board.height() {
+            for x in 0..board.width() {
+                out.push(if visible.contains(&Point::new(x, y)) { '*' } else { '?' });
+            }
+            out.push('\n');
+        }
+        out
+    }
+
+    #[test]
+    fn test_open_area_is_a_circle() {
+        let terrains = terrains();
+        let floor = terrains.find("Floor").unwrap();
+        let board = Board::new(terrains, 21, 21, floor);
+
+        let origin = Point::new(10, 10);
+        let visible = shadowcast(&board, &origin, 6);
+        let circle: HashSet<Point> = CircleIter::new(origin, 6).collect();
+        assert_eq!(visible, circle);
+    }
+
+    #[test]
+    fn test_walls_block_sight() {
+        let text = "\
+#######
+#.....#
+#.###.#
+#.#.#.#
+#.###.#
+#.....#
+#######
+";
+        let board = Board::parse_ascii(terrains(), text, &Legend::new()).unwrap();
+        let visible = shadowcast(&board, &Point::new(1, 1), 20);
+
+        // The near side of the inner walls is seen, the room inside them
+        // isn't, and neither is the far side of the corridor.
+        let expected = "\
+*******
+*******
+*******
+***????
+***????
+***????
+***????
+";
+        assert_eq!(draw(&board, &visible), expected);
+    }
+
+    #[test]
+    fn test_closed_doors_block_sight() {
+        let terrains = terrains();
+        let floor = terrains.find("Floor").unwrap();
+        let mut legend = Legend::new();
+        legend.add('+', Feature::Door(DoorState::Closed), floor);
+
+        let mut board = Board::parse_ascii(terrains, "#####\n#.+.#\n#####\n", &legend).unwrap();
+        let origin = Point::new(1, 1);
+        assert!(shadowcast(&board, &origin, 5).contains(&Point::new(2, 1)));
+        assert!(!shadowcast(&board, &origin, 5).contains(&Point::new(3, 1)));
+
+        board.toggle_door(&Point::new(2, 1)).unwrap();
+        assert!(shadowcast(&board, &origin, 5).contains(&Point::new(3, 1)));
+    }
+
+    #[test]
+    fn test_origin_off_board() {
+        let board = Board::parse_ascii(terrains(), "...\n...\n", &Legend::new()).unwrap();
+        assert!(shadowcast(&board, &Point::new(-5, -5), 10).is_empty());
+    }
+}
@@ -0,0 +1,28 @@
+#[macro_use] extern crate glium;
+extern crate bincode;
+extern crate cgmath;
+extern crate crypto;
+extern crate glium_text;
+extern crate glob;
+extern crate image;
+extern crate rand;
+extern crate rusttype;
+extern crate texture_packer;
+extern crate toml;
+extern crate serde;
+#[macro_use] extern crate serde_derive;
+extern crate serde_json;
+extern crate xml;
+
+pub mod atlas;
+pub mod autotile;
+pub mod render;
+pub mod board;
+pub mod fov;
+pub mod terrain;
+pub mod util;
+pub mod ui;
+pub mod point;
+
+#[cfg(test)]
+mod testing;
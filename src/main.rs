@@ -1,38 +1,22 @@
-#[macro_use] extern crate glium;
-extern crate bincode;
-extern crate cgmath;
-extern crate crypto;
-extern crate glium_text;
-extern crate glob;
-extern crate image;
-extern crate rand;
-extern crate rusttype;
-extern crate texture_packer;
-extern crate toml;
-extern crate serde;
-#[macro_use] extern crate serde_derive;
-
-mod atlas;
-mod render;
-mod board;
-mod terrain;
-mod util;
-mod ui;
-mod point;
+extern crate autotile;
+extern crate glium;
 
+use std::env;
+use std::rc::Rc;
 use std::thread;
 use std::time::{Duration, Instant};
 
 use glium::glutin;
 use glium::glutin::{VirtualKeyCode, ElementState};
 
-use point::{Point, RectangleIter};
+use autotile::point::{Point, RectangleIter};
 
-use board::Board;
-use terrain::Terrain;
-use ui::*;
-use ui::elements::UiList;
-use render::{Action, RenderContext};
+use autotile::board::{tiled, Board, LAYERS};
+use autotile::fov;
+use autotile::terrain::{DoorState, Feature, TerrainRegistry};
+use autotile::ui::*;
+use autotile::ui::elements::UiList;
+use autotile::render::{Action, RenderContext};
 
 pub struct InvLayer {
     list: UiList,
@@ -87,28 +71,90 @@ impl UiQuery for InvLayer {
     }
 }
 
-fn main() {
-    let mut board = Board::new(20, 20, Terrain::Wall);
+fn make_board(terrains: Rc<TerrainRegistry>) -> Board {
+    let wall = terrains.find("Wall").unwrap();
+    let floor = terrains.find("Floor").unwrap();
+    let important = terrains.find("Important").unwrap();
+
+    let mut board = Board::new(terrains.clone(), 20, 20, wall);
 
     for pos in RectangleIter::new(Point::new(2, 2), Point::new(8, 8)) {
-        board.set(&pos, Terrain::Floor);
+        board.set(&pos, floor).unwrap();
     }
 
     for pos in RectangleIter::new(Point::new(6, 6), Point::new(10, 10)) {
-        board.set(&pos, Terrain::Important);
+        board.set(&pos, important).unwrap();
     }
 
-    board.set(&Point::new(6, 6), Terrain::Wall);
-    // board.set(&Point::new(5, 6), Terrain::Wall);
-    // board.set(&Point::new(7, 6), Terrain::Wall);
-    board.set(&Point::new(6, 5), Terrain::Wall);
-    board.set(&Point::new(6, 7), Terrain::Wall);
+    board.set(&Point::new(6, 6), wall).unwrap();
+    // board.set(&Point::new(5, 6), wall);
+    // board.set(&Point::new(7, 6), wall);
+    board.set(&Point::new(6, 5), wall).unwrap();
+    board.set(&Point::new(6, 7), wall).unwrap();
+
+    board.set_feature(&Point::new(5, 5), Feature::Door(DoorState::Closed)).unwrap();
+    board.set_feature(&Point::new(3, 3), Feature::DownStair).unwrap();
+
+    board
+}
+
+/// How far the player can see, in cells.
+const SIGHT_RADIUS: i32 = 8;
 
-    let mut context = RenderContext::new();
+/// Finds a cell for the player to start on, the first one that doesn't
+/// block sight. Goes by rows and columns rather than a `RectangleIter`,
+/// which never ends on an empty board.
+fn start_position(board: &Board) -> Point {
+    for y in 0..board.height() {
+        for x in 0..board.width() {
+            let pos = Point::new(x, y);
+            if !fov::blocks_sight(board, &pos) {
+                return pos;
+            }
+        }
+    }
+    Point::new(0, 0)
+}
 
-    context.update(&board);
+fn main() {
+    let terrains = match TerrainRegistry::from_toml("data/terrain.toml") {
+        Ok(terrains) => Rc::new(terrains),
+        Err(e)       => panic!("Failed to load terrain: {}", e),
+    };
+
+    // Load the board or Tiled map given on the command line, if any.
+    let mut board = match env::args().nth(1) {
+        Some(path) => {
+            let loaded = if path.ends_with(".json") || path.ends_with(".tmx") {
+                tiled::import(terrains, &path).map(|map| map.board)
+            } else {
+                Board::load(terrains, &path)
+            };
+            match loaded {
+                Ok(board) => board,
+                Err(e)    => panic!("Failed to load board: {}", e),
+            }
+        },
+        None => make_board(terrains),
+    };
+
+    let mut context = match RenderContext::new(board.terrains()) {
+        Ok(context) => context,
+        Err(e)      => panic!("Failed to load tiles: {}", e),
+    };
+
+    if let Err(e) = context.update(&mut board) {
+        panic!("Failed to load chunks: {}", e);
+    }
+
+    let mut player = start_position(&board);
+    let visible = fov::shadowcast(&board, &player, SIGHT_RADIUS);
+    board.explore(&visible);
+    context.set_visible(visible);
 
     context.start_loop(|ctxt| {
+        let mut turn_taken = false;
+
         // polling and handling the events received by the window
         for event in ctxt.poll_events() {
             match event {
@@ -143,20 +189,51 @@ fn main() {
                             ctxt.next_line();
                         },
                         VirtualKeyCode::Left => {
-                            ctxt.viewport.camera.0 -= 48;
+                            ctxt.viewport.camera.0 -= ctxt.viewport.tile_size.0 as i32;
                         },
                         VirtualKeyCode::Up => {
-                            ctxt.viewport.camera.1 -= 48;
+                            ctxt.viewport.camera.1 -= ctxt.viewport.tile_size.1 as i32;
                         },
                         VirtualKeyCode::Down => {
-                            ctxt.viewport.camera.1 += 48;
+                            ctxt.viewport.camera.1 += ctxt.viewport.tile_size.1 as i32;
                         },
                         VirtualKeyCode::Right => {
-                            ctxt.viewport.camera.0 += 48;
+                            ctxt.viewport.camera.0 += ctxt.viewport.tile_size.0 as i32;
                         },
                         VirtualKeyCode::R => {
                             ctxt.refresh_shaders();
                         },
+                        VirtualKeyCode::D => {
+                            // Opens or closes every door on the board.
+                            let doors: Vec<Point> = board.features()
+                                .filter(|&(_, feature)| match *feature { Feature::Door(..) => true, _ => false })
+                                .map(|(pos, _)| *pos)
+                                .collect();
+                            for pos in doors.iter() {
+                                board.toggle_door(pos).unwrap();
+                            }
+                            turn_taken = true;
+                        },
+                        VirtualKeyCode::H |
+                        VirtualKeyCode::J |
+                        VirtualKeyCode::K |
+                        VirtualKeyCode::L => {
+                            let dir = match code {
+                                VirtualKeyCode::H => (-1, 0),
+                                VirtualKeyCode::J => (0, 1),
+                                VirtualKeyCode::K => (0, -1),
+                                _                 => (1, 0),
+                            };
+                            let next = player + dir;
+                            if !fov::blocks_sight(&board, &next) {
+                                player = next;
+                                turn_taken = true;
+                            }
+                        },
+                        VirtualKeyCode::Key1 => ctxt.toggle_layer(LAYERS[0]),
+                        VirtualKeyCode::Key2 => ctxt.toggle_layer(LAYERS[1]),
+                        VirtualKeyCode::Key3 => ctxt.toggle_layer(LAYERS[2]),
+                        VirtualKeyCode::Key4 => ctxt.toggle_layer(LAYERS[3]),
                         _ => (),
                     }
                 },
@@ -164,6 +241,15 @@ fn main() {
             }
         }
 
+        if turn_taken {
+            let visible = fov::shadowcast(&board, &player, SIGHT_RADIUS);
+            board.explore(&visible);
+            ctxt.set_visible(visible);
+        }
+
+        if let Err(e) = ctxt.refresh(&mut board) {
+            panic!("Failed to load chunks: {}", e);
+        }
         ctxt.render();
 
         Action::Continue
@@ -0,0 +1,54 @@
+use std::error::Error;
+use std::fmt;
+
+use atlas::error::AtlasError;
+use terrain::TerrainError;
+
+/// Everything that can go wrong while setting up the renderers.
+#[derive(Debug)]
+pub enum RenderError {
+    /// A tile atlas couldn't be loaded, or lacks a tile that is drawn.
+    Atlas(AtlasError),
+
+    /// The terrain config names tiles the atlases don't have.
+    Terrain(TerrainError),
+}
+
+pub type RenderResult<T> = Result<T, RenderError>;
+
+impl From<AtlasError> for RenderError {
+    fn from(e: AtlasError) -> RenderError {
+        RenderError::Atlas(e)
+    }
+}
+
+impl From<TerrainError> for RenderError {
+    fn from(e: TerrainError) -> RenderError {
+        RenderError::Terrain(e)
+    }
+}
+
+impl fmt::Display for RenderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RenderError::Atlas(ref e)   => write!(f, "{}", e),
+            RenderError::Terrain(ref e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for RenderError {
+    fn description(&self) -> &str {
+        match *self {
+            RenderError::Atlas(ref e)   => e.description(),
+            RenderError::Terrain(ref e) => e.description(),
+        }
+    }
+
+    fn cause(&self) -> Option<&Error> {
+        match *self {
+            RenderError::Atlas(ref e)   => Some(e),
+            RenderError::Terrain(ref e) => Some(e),
+        }
+    }
+}
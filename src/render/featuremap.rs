@@ -0,0 +1,132 @@
+use glium;
+use glium::backend::Facade;
+use glium::index::PrimitiveType;
+
+use atlas::*;
+use board::Board;
+use point::Point;
+use render::{self, Renderable, RenderResult, Viewport, Vertex, QUAD, QUAD_INDICES};
+use terrain::TerrainRegistry;
+
+#[derive(Copy, Clone)]
+struct Instance {
+    map_coord: [u32; 2],
+    tex_offset: [f32; 2],
+    tex_ratio: [f32; 2],
+    sprite_size: [u32; 2],
+}
+
+implement_vertex!(Instance, map_coord, tex_offset, tex_ratio, sprite_size);
+
+/// Draws the doors and stairs on a board, with the tiles named in the
+/// `[features]` table of `terrain.toml`. Features are drawn like sprites,
+/// centered on their cell.
+pub struct FeatureMap {
+    features: Vec<(usize, Point)>,
+
+    indices: glium::IndexBuffer<u16>,
+    vertices: glium::VertexBuffer<Vertex>,
+    program: glium::Program,
+
+    tile_manager: TileManager,
+}
+
+/// Gets the tile and cell of each feature on the board, leaving out features
+/// without a tile the atlas has.
+fn make_map(board: &Board, tile_manager: &TileManager) -> Vec<(usize, Point)> {
+    let mut res = Vec::new();
+
+    for (pos, feature) in board.features() {
+        let idx = match board.terrains().feature_tile(feature) {
+            Some(name) => match tile_manager.index_of(name) {
+                Ok(idx) => idx,
+                Err(_)  => continue,
+            },
+            None       => continue,
+        };
+        res.push((idx, *pos));
+    }
+
+    res
+}
+
+impl FeatureMap {
+    pub fn new<F: Facade>(display: &F, terrains: &TerrainRegistry) -> RenderResult<Self> {
+        let tile_manager = TileManager::from_config(display, "data/features.toml")?;
+        terrains.check_feature_tiles(&tile_manager)?;
+
+        let vertices = glium::VertexBuffer::immutable(display, &QUAD).unwrap();
+        let indices = glium::IndexBuffer::immutable(display, PrimitiveType::TrianglesList, &QUAD_INDICES).unwrap();
+
+        let program = render::load_program(display, "sprite.vert", "sprite.frag").unwrap();
+
+        Ok(FeatureMap {
+            features: Vec::new(),
+            indices: indices,
+            vertices: vertices,
+            program: program,
+            tile_manager: tile_manager,
+        })
+    }
+
+    pub fn update(&mut self, board: &Board) {
+        self.features = make_map(board, &self.tile_manager);
+    }
+
+    /// Makes instances for the features on the given page that are on
+    /// screen, or just off it.
+    fn create_instances<F>(&self, display: &F, viewport: &Viewport, pass: usize, msecs: u64) -> glium::VertexBuffer<Instance>
+        where F: glium::backend::Facade {
+
+        let data = self.features.iter()
+            .filter(|&&(_, c)| viewport.is_cell_visible(&c, 1))
+            .filter(|&&(idx, _)| self.tile_manager.get_tile_texture_idx(idx, msecs) == pass)
+            .map(|&(idx, c)| {
+                let (tx, ty) = self.tile_manager.get_texture_offset(idx, msecs);
+                let (sx, sy) = self.tile_manager.get_tile_texture_size(idx);
+                let tex_ratio = self.tile_manager.get_sprite_tex_ratio(idx, msecs);
+
+                Instance { map_coord: [c.x as u32, c.y as u32],
+                           tex_offset: [tx, ty],
+                           tex_ratio: tex_ratio,
+                           sprite_size: [sx, sy], }
+            }).collect::<Vec<Instance>>();
+
+        glium::VertexBuffer::dynamic(display, &data).unwrap()
+    }
+}
+
+impl Renderable for FeatureMap {
+    fn render<F, S>(&self, display: &F, target: &mut S, viewport: &Viewport, msecs: u64)
+        where F: glium::backend::Facade, S: glium::Surface {
+
+        let (proj, scissor) = viewport.main_window();
+
+        for pass in 0..self.tile_manager.passes() {
+            let texture = self.tile_manager.get_texture(pass);
+
+            let uniforms = uniform! {
+                matrix: proj,
+                tile_size: [viewport.tile_size.0, viewport.tile_size.1],
+                tex: texture.sampled()
+                    .wrap_function(glium::uniforms::SamplerWrapFunction::Clamp)
+                    .minify_filter(glium::uniforms::MinifySamplerFilter::Nearest)
+                    .magnify_filter(glium::uniforms::MagnifySamplerFilter::Nearest),
+            };
+
+            let instances = self.create_instances(display, viewport, pass, msecs);
+
+            let params = glium::DrawParameters {
+                blend: glium::Blend::alpha_blending(),
+                scissor: Some(scissor),
+                .. Default::default()
+            };
+
+            target.draw((&self.vertices, instances.per_instance().unwrap()),
+                        &self.indices,
+                        &self.program,
+                        &uniforms,
+                        &params).unwrap();
+        }
+    }
+}
@@ -0,0 +1,183 @@
+//! Golden image tests for the autotiler.
+//!
+//! Every board in `data/test/boards` is drawn with the software renderer and
+//! compared against the PNG of the same name in `data/test/golden`. On a
+//! mismatch the rendered image and a diff highlighting the changed pixels are
+//! written to `data/test/diff`.
+//!
+//! Run the tests with `AUTOTILE_BLESS=1` set to overwrite the golden images
+//! with the current output. A board without a golden image fails the test
+//! unless it is blessed, so new boards need their image committed.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use glob;
+use image::{self, Rgba, RgbaImage};
+
+use atlas::TileImages;
+use board::{Board, Legend};
+use point::Point;
+use render::software;
+use terrain::TerrainRegistry;
+use testing;
+use util;
+
+const BOARDS_GLOB: &'static str = "data/test/boards/*.txt";
+const GOLDEN_DIR: &'static str = "data/test/golden";
+const DIFF_DIR: &'static str = "data/test/diff";
+
+/// Largest difference allowed in any color channel before a pixel counts as
+/// changed.
+const TOLERANCE: u8 = 2;
+
+struct Mismatch {
+    pixels: usize,
+    diff: RgbaImage,
+}
+
+/// Compares two images of the same size, returning a diff image with the
+/// changed pixels marked in red if any channel differs by more than
+/// `tolerance`.
+fn compare_images(expected: &RgbaImage, actual: &RgbaImage, tolerance: u8) -> Option<Mismatch> {
+    let (w, h) = expected.dimensions();
+    let mut diff = RgbaImage::new(w, h);
+    let mut pixels = 0;
+
+    for y in 0..h {
+        for x in 0..w {
+            let a = expected.get_pixel(x, y).data;
+            let b = actual.get_pixel(x, y).data;
+
+            let changed = a.iter().zip(b.iter()).any(|(ca, cb)| {
+                (*ca as i32 - *cb as i32).abs() > tolerance as i32
+            });
+
+            let pixel = if changed {
+                pixels += 1;
+                Rgba { data: [255, 0, 0, 255] }
+            } else {
+                // Keep a faded copy of the expected image for context.
+                let luma = ((a[0] as u32 + a[1] as u32 + a[2] as u32) / 3) as u8;
+                Rgba { data: [luma / 3, luma / 3, luma / 3, 255] }
+            };
+
+            diff.put_pixel(x, y, pixel);
+        }
+    }
+
+    if pixels == 0 {
+        None
+    } else {
+        Some(Mismatch {
+            pixels: pixels,
+            diff: diff,
+        })
+    }
+}
+
+fn output_path(dir: &str, name: &str, suffix: &str) -> PathBuf {
+    let mut path = PathBuf::from(dir);
+    path.push(format!("{}{}.png", name, suffix));
+    path
+}
+
+/// Renders a single board and checks it against its golden image. Returns a
+/// description of the failure, if any.
+fn check_board(tiles: &TileImages, terrains: &Rc<TerrainRegistry>, board_path: &Path, bless: bool) -> Option<String> {
+    let name = board_path.file_stem().unwrap().to_str().unwrap().to_string();
+    let board = match Board::parse_ascii(terrains.clone(), &util::read_string(board_path.to_str().unwrap()), &Legend::new()) {
+        Ok(board) => board,
+        Err(e)    => panic!("{}: {}", board_path.display(), e),
+    };
+    let actual = software::render_board(&board, tiles, 0);
+
+    let golden_path = output_path(GOLDEN_DIR, &name, "");
+
+    if bless {
+        fs::create_dir_all(GOLDEN_DIR).unwrap();
+        actual.save(&golden_path).unwrap();
+        println!("Wrote golden image {}", golden_path.display());
+        return None;
+    }
+
+    if !golden_path.exists() {
+        fs::create_dir_all(DIFF_DIR).unwrap();
+        let actual_path = output_path(DIFF_DIR, &name, ".actual");
+        actual.save(&actual_path).unwrap();
+        return Some(format!("{}: no golden image at {}, see {} and run with AUTOTILE_BLESS=1 to accept it",
+                            name, golden_path.display(), actual_path.display()));
+    }
+
+    let expected = image::open(&golden_path).unwrap().to_rgba();
+
+    let failure = if expected.dimensions() != actual.dimensions() {
+        Some(format!("{}: expected size {:?}, got {:?}",
+                     name, expected.dimensions(), actual.dimensions()))
+    } else {
+        compare_images(&expected, &actual, TOLERANCE).map(|mismatch| {
+            fs::create_dir_all(DIFF_DIR).unwrap();
+            let diff_path = output_path(DIFF_DIR, &name, ".diff");
+            mismatch.diff.save(&diff_path).unwrap();
+            format!("{}: {} pixels differ, see {}", name, mismatch.pixels, diff_path.display())
+        })
+    };
+
+    if failure.is_some() {
+        fs::create_dir_all(DIFF_DIR).unwrap();
+        actual.save(output_path(DIFF_DIR, &name, ".actual")).unwrap();
+    }
+
+    failure
+}
+
+#[test]
+fn test_golden_boards() {
+    let tiles = testing::tile_images();
+    let terrains = Rc::new(TerrainRegistry::from_toml("data/terrain.toml").unwrap());
+    let bless = env::var("AUTOTILE_BLESS").is_ok();
+
+    let mut checked = 0;
+    let mut failures = Vec::new();
+
+    for entry in glob::glob(BOARDS_GLOB).unwrap() {
+        let board_path = entry.unwrap();
+        if let Some(failure) = check_board(tiles, &terrains, &board_path, bless) {
+            failures.push(failure);
+        }
+        checked += 1;
+    }
+
+    assert!(checked > 0, "No boards found matching {}", BOARDS_GLOB);
+    assert!(failures.is_empty(), "Golden image mismatches:\n{}", failures.join("\n"));
+}
+
+#[test]
+fn test_compare_within_tolerance() {
+    let a = RgbaImage::from_pixel(2, 2, Rgba { data: [100, 100, 100, 255] });
+    let b = RgbaImage::from_pixel(2, 2, Rgba { data: [101, 99, 100, 255] });
+    assert!(compare_images(&a, &b, TOLERANCE).is_none());
+}
+
+#[test]
+fn test_compare_counts_changed_pixels() {
+    let a = RgbaImage::from_pixel(2, 2, Rgba { data: [100, 100, 100, 255] });
+    let mut b = a.clone();
+    b.put_pixel(1, 0, Rgba { data: [0, 100, 100, 255] });
+
+    let mismatch = compare_images(&a, &b, TOLERANCE).unwrap();
+    assert_eq!(mismatch.pixels, 1);
+    assert_eq!(mismatch.diff.get_pixel(1, 0).data, [255, 0, 0, 255]);
+}
+
+#[test]
+fn test_parse_board() {
+    let terrains = Rc::new(TerrainRegistry::from_toml("data/terrain.toml").unwrap());
+    let board = Board::parse_ascii(terrains, "#.\n$#\n", &Legend::new()).unwrap();
+    assert_eq!(board.width(), 2);
+    assert_eq!(board.height(), 2);
+    assert_eq!(board.terrain_at(&Point::new(1, 0)).name, "Floor");
+    assert_eq!(board.terrain_at(&Point::new(0, 1)).name, "Important");
+}
@@ -9,17 +9,25 @@ use glium::backend::glutin_backend::GlutinFacade;
 use glium::backend::Facade;
 
 
-use board::Board;
-use point::{Point, CircleIter, RectangleIter};
+use board::{chunk, Board, BoardResult, Layer, CHUNK_SIZE};
+use point::Point;
+use terrain::TerrainRegistry;
 use ui::*;
 use util;
 use self::background::Background;
+use self::featuremap::FeatureMap;
 use self::shadowmap::ShadowMap;
 use self::spritemap::SpriteMap;
 use self::tilemap::TileMap;
+pub use self::error::{RenderError, RenderResult};
 pub use self::viewport::Viewport;
 
 mod background;
+mod error;
+mod featuremap;
+#[cfg(test)]
+mod golden;
+pub mod software;
 mod shadowmap;
 mod spritemap;
 mod tilemap;
@@ -57,6 +65,7 @@ pub struct RenderContext {
     background: Background,
     spritemap: SpriteMap,
     tilemap: TileMap,
+    featuremap: FeatureMap,
     shadowmap: ShadowMap,
 
     accumulator: FpsAccumulator,
@@ -64,7 +73,9 @@ pub struct RenderContext {
 }
 
 impl RenderContext {
-    pub fn new() -> Self{
+    /// Opens the window and loads the tile atlases, checking they have the
+    /// tiles `terrains` draws its terrain and features with.
+    pub fn new(terrains: &TerrainRegistry) -> RenderResult<Self> {
         let display = glutin::WindowBuilder::new()
             .with_vsync()
             .with_dimensions(SCREEN_WIDTH, SCREEN_HEIGHT)
@@ -73,17 +84,13 @@ impl RenderContext {
             .unwrap();
 
         let bg = Background::new(&display);
-        let ui = Ui::new(&display);
-        let tile = TileMap::new(&display);
+        let tile = TileMap::new(&display, terrains)?;
+        let ui = Ui::new(&display, tile.packing());
+        let feature = FeatureMap::new(&display, terrains)?;
 
-        let mut vis = HashSet::new();
-        for point in CircleIter::new(Point::new(6, 6), 5) {
-            vis.insert(point);
-        }
-
-        let shadow = ShadowMap::new(&display, RectangleIter::new(Point::new(0, 0), Point::new(20, 20)), vis);
+        let shadow = ShadowMap::new(&display);
 
-        let sprite = SpriteMap::new(&display);
+        let sprite = SpriteMap::new(&display)?;
 
         let scale = display.get_window().unwrap().hidpi_factor();
 
@@ -93,19 +100,21 @@ impl RenderContext {
             position: (0, 0),
             size: (SCREEN_WIDTH, SCREEN_HEIGHT),
             scale: scale,
-            camera: (0, 0)
+            camera: (0, 0),
+            tile_size: tile.tile_size(),
         };
 
-        RenderContext {
+        Ok(RenderContext {
             backend: display,
             background: bg,
             ui: ui,
             shadowmap: shadow,
             spritemap: sprite,
             tilemap: tile,
+            featuremap: feature,
             accumulator: accumulator,
             viewport: viewport,
-        }
+        })
     }
 
     pub fn start_loop<F>(&mut self, mut callback: F) where F: FnMut(&mut RenderContext) -> Action {
@@ -121,8 +130,49 @@ impl RenderContext {
         }
     }
 
-    pub fn update(&mut self, board: &Board) {
-        self.tilemap.update(board);
+    /// Redraws the whole board, loading the chunks on screen. Changes made
+    /// afterwards can be drawn with `refresh`.
+    pub fn update(&mut self, board: &mut Board) -> BoardResult<()> {
+        self.load_visible(board)?;
+        board.take_dirty();
+        self.tilemap.update(board, &self.viewport);
+        self.featuremap.update(board);
+        self.shadowmap.prepare(&self.backend, board, &self.viewport);
+        Ok(())
+    }
+
+    /// Redraws only the cells of `board` that changed since they were last
+    /// drawn, and loads and draws the chunks that came on screen. Call once
+    /// a frame.
+    pub fn refresh(&mut self, board: &mut Board) -> BoardResult<()> {
+        self.load_visible(board)?;
+        let changed = board.take_dirty();
+        self.tilemap.patch(board, &changed, &self.viewport);
+        if !changed.is_empty() {
+            self.featuremap.update(board);
+        }
+        self.shadowmap.prepare(&self.backend, board, &self.viewport);
+        Ok(())
+    }
+
+    fn load_visible(&self, board: &mut Board) -> BoardResult<()> {
+        let (first, last) = tilemap::visible_chunks(&self.viewport);
+        board.load_area(&chunk::chunk_origin(&first),
+                        &(chunk::chunk_origin(&last) + (CHUNK_SIZE - 1, CHUNK_SIZE - 1)))
+    }
+
+    /// Sets the cells in view, as found by `fov::shadowcast`. Cells out of
+    /// view are dimmed and drawn in grays if the board has them explored and
+    /// hidden if not, so mark them explored with `Board::explore` first.
+    /// Nothing is shaded until this is called.
+    pub fn set_visible(&mut self, visible: HashSet<Point>) {
+        self.shadowmap.update(visible);
+    }
+
+    /// Shows the layer if it's hidden, or hides it.
+    pub fn toggle_layer(&mut self, layer: Layer) {
+        let visible = self.tilemap.is_layer_visible(layer);
+        self.tilemap.set_layer_visible(layer, !visible);
     }
 
     pub fn refresh_shaders(&mut self) {
@@ -135,8 +185,11 @@ impl RenderContext {
 
         let millis = self.accumulator.millis_since_start();
 
+        self.tilemap.prepare(&self.backend, millis);
+
         self.background.render(&self.backend, &mut target, &self.viewport, millis);
-        self.tilemap.render(&self.backend, &mut target, &self.viewport, millis);
+        self.tilemap.render_with_fog(&mut target, &self.viewport, self.shadowmap.fog());
+        self.featuremap.render(&self.backend, &mut target, &self.viewport, millis);
         self.spritemap.render(&self.backend, &mut target, &self.viewport, millis);
         self.shadowmap.render(&self.backend, &mut target, &self.viewport, millis);
         self.ui.render(&self.backend, &mut target, &self.viewport, millis);
@@ -150,6 +203,7 @@ impl RenderContext {
             size: (w, h),
             scale: self.viewport.scale,
             camera: self.viewport.camera,
+            tile_size: self.viewport.tile_size,
         };
     }
 
@@ -5,6 +5,7 @@ use glium;
 use glium::backend::Facade;
 use glium::index::PrimitiveType;
 
+use board::Board;
 use point::Point;
 use point::RectangleIter;
 use render::{self, Renderable, Vertex, Viewport, QUAD, QUAD_INDICES};
@@ -12,56 +13,176 @@ use render::{self, Renderable, Vertex, Viewport, QUAD, QUAD_INDICES};
 #[derive(Clone, Copy)]
 struct Instance {
     map_coord: [i32; 2],
-    tile_index: i8,
+    color: [f32; 4],
 }
 
-implement_vertex!(Instance, map_coord, tile_index);
+implement_vertex!(Instance, map_coord, color);
 
+/// What the player knows of a cell.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Fog {
+    Visible,
+
+    /// Explored before but out of view now.
+    Remembered,
+
+    Unexplored,
+}
+
+impl Fog {
+    fn of(board: &Board, visible: &HashSet<Point>, pos: &Point) -> Fog {
+        if visible.contains(pos) {
+            Fog::Visible
+        } else if board.is_explored(pos) {
+            Fog::Remembered
+        } else {
+            Fog::Unexplored
+        }
+    }
+
+    /// Gets the color blended over the cell, if any. Remembered cells are
+    /// dimmed here, and their tiles drawn in grays using `fog_image`.
+    fn color(&self) -> Option<[f32; 4]> {
+        match *self {
+            Fog::Visible    => None,
+            Fog::Remembered => Some([0.0, 0.0, 0.0, 0.5]),
+            Fog::Unexplored => Some([0.0, 0.0, 0.0, 1.0]),
+        }
+    }
+
+    /// Gets how much the tile shader washes out the colors of the cell.
+    fn desaturation(&self) -> u8 {
+        match *self {
+            Fog::Remembered => 255,
+            _               => 0,
+        }
+    }
+}
+
+/// Covers the cells on screen that aren't in view, hiding those that were
+/// never explored and dimming the rest.
 pub struct ShadowMap {
-    instances: glium::VertexBuffer<Instance>,
+    /// The cells in view, or `None` to draw no shadows at all.
+    visible: Option<HashSet<Point>>,
+
+    /// The first and last cell on screen when the instances were made, and
+    /// the instances, `None` until they are made again.
+    area: (Point, Point),
+    instances: Option<glium::VertexBuffer<Instance>>,
+
+    /// How much to wash out the tiles of each cell in `area`, made along
+    /// with the instances.
+    fog: Option<glium::texture::Texture2d>,
 
     indices: glium::IndexBuffer<u16>,
     vertices: glium::VertexBuffer<Vertex>,
     program: glium::Program,
 }
 
-fn make_instances<F: Facade>(display: &F, area: RectangleIter, visible: HashSet<Point>) -> glium::VertexBuffer<Instance> {
+/// Checks if `area` has no cells, as when the window is resized to nothing
+/// and the last cell on screen is before the first.
+fn is_empty(area: (Point, Point)) -> bool {
+    area.1.x < area.0.x || area.1.y < area.0.y
+}
+
+fn make_instances<F: Facade>(display: &F, area: (Point, Point), board: &Board, visible: &HashSet<Point>) -> glium::VertexBuffer<Instance> {
     let mut instances = Vec::new();
-    for point in area {
-        if !visible.contains(&point) {
+    for point in RectangleIter::new(area.0, area.1) {
+        if let Some(color) = Fog::of(board, visible, &point).color() {
             instances.push(Instance {
                 map_coord: [point.x, point.y],
-                tile_index: 4,
+                color: color,
             })
         }
     }
     glium::VertexBuffer::immutable(display, &instances).unwrap()
 }
 
+/// Gets an RGBA image with a pixel for each cell from `area.0` to `area.1`,
+/// row by row, whose red channel is how much the tile shader washes out the
+/// colors of the cell. `area` must not be empty.
+fn fog_image(area: (Point, Point), board: &Board, visible: &HashSet<Point>) -> Vec<u8> {
+    let mut data = Vec::new();
+    for point in RectangleIter::new(area.0, area.1) {
+        data.extend_from_slice(&[Fog::of(board, visible, &point).desaturation(), 0, 0, 0]);
+    }
+    data
+}
+
 impl ShadowMap {
-    pub fn new<F: Facade>(display: &F, area: RectangleIter, visible: HashSet<Point>) -> Self {
+    pub fn new<F: Facade>(display: &F) -> Self {
         let vertices = glium::VertexBuffer::immutable(display, &QUAD).unwrap();
         let indices = glium::IndexBuffer::immutable(display, PrimitiveType::TrianglesList, &QUAD_INDICES).unwrap();
         let program = render::load_program(display, "shadow.vert", "shadow.frag").unwrap();
 
         ShadowMap {
-            instances: make_instances(display, area, visible),
+            visible: None,
+            area: (Point::new(0, 0), Point::new(-1, -1)),
+            instances: None,
+            fog: None,
             vertices: vertices,
             indices: indices,
             program: program,
         }
     }
+
+    /// Sets the cells in view, as found by `fov::shadowcast`. Every other
+    /// cell is shaded, depending on whether the board has it explored.
+    pub fn update(&mut self, visible: HashSet<Point>) {
+        self.visible = Some(visible);
+        self.instances = None;
+        self.fog = None;
+    }
+
+    /// Makes the instances and fog texture for the cells on screen if the
+    /// cells in view changed or the screen moved since they were last made.
+    /// Call before rendering.
+    pub fn prepare<F: Facade>(&mut self, display: &F, board: &Board, viewport: &Viewport) {
+        let visible = match self.visible {
+            Some(ref visible) => visible,
+            None              => return,
+        };
+
+        let area = viewport.visible_cells();
+        if self.instances.is_some() && area == self.area {
+            return;
+        }
+
+        self.area = area;
+        if is_empty(area) {
+            self.instances = None;
+            self.fog = None;
+            return;
+        }
+
+        self.instances = Some(make_instances(display, area, board, visible));
+
+        let size = ((area.1.x - area.0.x + 1) as u32, (area.1.y - area.0.y + 1) as u32);
+        let image = glium::texture::RawImage2d::from_raw_rgba(fog_image(area, board, visible), size);
+        self.fog = Some(glium::texture::Texture2d::new(display, image).unwrap());
+    }
+
+    /// Gets the texture the tile shader washes out remembered cells with,
+    /// and the cell its first pixel is for. `None` when nothing is shaded.
+    pub fn fog(&self) -> Option<(&glium::texture::Texture2d, Point)> {
+        self.fog.as_ref().map(|fog| (fog, self.area.0))
+    }
 }
 
 impl Renderable for ShadowMap {
     fn render<F, S>(&self, display: &F, target: &mut S, viewport: &Viewport, msecs: u64)
         where F: glium::backend::Facade, S: glium::Surface {
 
+        let instances = match self.instances {
+            Some(ref instances) => instances,
+            None                => return,
+        };
+
         let (proj, scissor) = viewport.main_window();
 
         let uniforms = uniform! {
             matrix: proj,
-            tile_size: [48u32; 2],
+            tile_size: [viewport.tile_size.0, viewport.tile_size.1],
         };
 
         let params = glium::DrawParameters {
@@ -70,10 +191,65 @@ impl Renderable for ShadowMap {
             .. Default::default()
         };
 
-        target.draw((&self.vertices, self.instances.per_instance().unwrap()),
+        target.draw((&self.vertices, instances.per_instance().unwrap()),
                     &self.indices,
                     &self.program,
                     &uniforms,
                     &params).unwrap();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::*;
+    use terrain::{TerrainRegistry, TERRAIN_NOTHING};
+
+    #[test]
+    fn test_fog() {
+        let mut board = Board::new(Rc::new(TerrainRegistry::new()), 3, 1, TERRAIN_NOTHING);
+        let seen: HashSet<Point> = vec![Point::new(0, 0), Point::new(1, 0)].into_iter().collect();
+        board.explore(&seen);
+        let visible: HashSet<Point> = vec![Point::new(1, 0)].into_iter().collect();
+
+        assert_eq!(Fog::of(&board, &visible, &Point::new(0, 0)), Fog::Remembered);
+        assert_eq!(Fog::of(&board, &visible, &Point::new(1, 0)), Fog::Visible);
+        assert_eq!(Fog::of(&board, &visible, &Point::new(2, 0)), Fog::Unexplored);
+        assert!(Fog::Visible.color().is_none());
+    }
+
+    #[test]
+    fn test_zero_size_viewport_is_empty() {
+        let viewport = Viewport {
+            position: (0, 0),
+            size: (0, 0),
+            scale: 1.0,
+            camera: (48, 48),
+            tile_size: (48, 48),
+        };
+        assert!(is_empty(viewport.visible_cells()));
+
+        let one_cell = Viewport { size: (1, 1), ..viewport };
+        assert!(!is_empty(one_cell.visible_cells()));
+    }
+
+    #[test]
+    fn test_fog_image() {
+        // A 3x2 area, so a texture filled column by column puts the cells
+        // below in the wrong texels.
+        let mut board = Board::new(Rc::new(TerrainRegistry::new()), 3, 2, TERRAIN_NOTHING);
+        let seen: HashSet<Point> = vec![Point::new(2, 0), Point::new(0, 1)].into_iter().collect();
+        board.explore(&seen);
+        let visible: HashSet<Point> = vec![Point::new(2, 0)].into_iter().collect();
+
+        let image = fog_image((Point::new(0, 0), Point::new(2, 1)), &board, &visible);
+        assert_eq!(image.len(), 3 * 2 * 4);
+        let texel = |x: usize, y: usize| &image[(y * 3 + x) * 4..(y * 3 + x + 1) * 4];
+        assert_eq!(texel(2, 0), &[0, 0, 0, 0]);
+        assert_eq!(texel(0, 1), &[255, 0, 0, 0]);
+
+        let washed: Vec<u8> = image.chunks(4).map(|pixel| pixel[0]).collect();
+        assert_eq!(washed, vec![0, 0, 0, 255, 0, 0]);
+    }
+}
@@ -0,0 +1,121 @@
+use image::{Pixel, RgbaImage};
+
+use atlas::TileImages;
+use autotile::{self, QUADRANTS};
+use board::{Board, LAYERS};
+use point::Point;
+
+/// Renders a board on the CPU the same way `TileMap` does on the GPU, so the
+/// result can be inspected or saved without a display. Cells are the grid
+/// size of the atlas config, and each quadrant is scaled to fit it. Every
+/// layer is drawn, bottom one first, skipping terrain whose tile the atlas
+/// doesn't have.
+pub fn render_board(board: &Board, tiles: &TileImages, msecs: u64) -> RgbaImage {
+    let (tile_w, tile_h) = tiles.grid_size();
+    let (quad_w, quad_h) = (tile_w / 2, tile_h / 2);
+    let width = board.width() as u32 * tile_w;
+    let height = board.height() as u32 * tile_h;
+    let mut target = RgbaImage::new(width, height);
+
+    for &layer in LAYERS.iter() {
+        let grid = board.layer(layer);
+
+        for pass in 0..tiles.passes() {
+            let page = tiles.get_texture(pass);
+
+            for i in 0..board.width() {
+                for j in 0..board.height() {
+                    let pos = Point::new(i, j);
+                    let idx = match board.terrain_in(layer, &pos).tile {
+                        Some(ref name) => match tiles.index_of(name) {
+                            Ok(idx) => idx,
+                            Err(_)  => continue,
+                        },
+                        None           => continue,
+                    };
+
+                    let edges = autotile::get_neighboring_edges(&grid, pos, board.terrains().connections());
+                    let cells = match tiles.get_autotile_layout(idx) {
+                        Some(layout) => autotile::get_quadrant_cells(layout, edges),
+                        None         => autotile::PLAIN_QUADRANT_CELLS,
+                    };
+
+                    for (quadrant, cell) in QUADRANTS.iter().zip(cells.iter()) {
+                        if tiles.get_quadrant_texture_idx(idx, *cell, msecs) != pass {
+                            continue;
+                        }
+
+                        let (sx, sy) = tiles.get_quadrant_offset_px(idx, *cell, msecs);
+                        let (sw, sh) = tiles.get_quadrant_size(idx);
+
+                        let dest = (pos.x as u32 * tile_w + (*quadrant as u32 % 2) * quad_w,
+                                    pos.y as u32 * tile_h + (*quadrant as u32 / 2) * quad_h);
+
+                        blit(&mut target, page, (sx, sy, sw, sh), (dest.0, dest.1, quad_w, quad_h));
+                    }
+                }
+            }
+        }
+    }
+
+    target
+}
+
+/// Alpha blends the `src_rect` area of `src` onto the `dest_rect` area of
+/// `target`, scaling it with nearest neighbor sampling like the tile shader
+/// does. Rects are x, y, width and height. Pixels falling outside either
+/// image are skipped.
+fn blit(target: &mut RgbaImage, src: &RgbaImage, src_rect: (u32, u32, u32, u32), dest_rect: (u32, u32, u32, u32)) {
+    let (src_w, src_h) = src.dimensions();
+    let (dest_w, dest_h) = target.dimensions();
+    let (src_x, src_y, sw, sh) = src_rect;
+    let (dest_x, dest_y, dw, dh) = dest_rect;
+
+    for y in 0..dh {
+        for x in 0..dw {
+            let (sx, sy) = (src_x + x * sw / dw, src_y + y * sh / dh);
+            let (dx, dy) = (dest_x + x, dest_y + y);
+
+            if sx >= src_w || sy >= src_h || dx >= dest_w || dy >= dest_h {
+                continue;
+            }
+
+            let mut pixel = *target.get_pixel(dx, dy);
+            pixel.blend(src.get_pixel(sx, sy));
+            target.put_pixel(dx, dy, pixel);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    #[test]
+    fn test_blit_scales_to_dest() {
+        // A 2x2 checker scaled up onto a 4x4 target.
+        let src = RgbaImage::from_fn(2, 2, |x, y| {
+            let v = if (x + y) % 2 == 0 { 255 } else { 0 };
+            Rgba { data: [v, v, v, 255] }
+        });
+        let mut target = RgbaImage::new(4, 4);
+
+        blit(&mut target, &src, (0, 0, 2, 2), (0, 0, 4, 4));
+
+        assert_eq!(target.get_pixel(1, 1).data, [255, 255, 255, 255]);
+        assert_eq!(target.get_pixel(2, 1).data, [0, 0, 0, 255]);
+        assert_eq!(target.get_pixel(3, 3).data, [255, 255, 255, 255]);
+    }
+
+    #[test]
+    fn test_blit_skips_outside_pixels() {
+        let src = RgbaImage::from_pixel(2, 2, Rgba { data: [9, 9, 9, 255] });
+        let mut target = RgbaImage::new(3, 3);
+
+        blit(&mut target, &src, (1, 1, 2, 2), (2, 2, 2, 2));
+
+        assert_eq!(target.get_pixel(2, 2).data, [9, 9, 9, 255]);
+        assert_eq!(target.get_pixel(1, 1).data, [0, 0, 0, 0]);
+    }
+}
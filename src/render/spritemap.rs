@@ -4,6 +4,7 @@ use glium::index::PrimitiveType;
 use cgmath;
 
 use atlas::*;
+use atlas::error::AtlasResult;
 use point::Point;
 use render::{self, Renderable, Viewport, Vertex, QUAD, QUAD_INDICES};
 
@@ -32,48 +33,55 @@ struct DrawSprite {
     color_mod: usize,
 }
 
-fn make_map() -> Vec<(DrawSprite, Point)> {
+fn make_map(tile_manager: &TileManager) -> AtlasResult<Vec<(DrawSprite, Point)>> {
     let mut res = Vec::new();
 
-    res.push((DrawSprite { idx: 0, color_mod: 0 }, Point::new(6, 6) ));
-    res.push((DrawSprite { idx: 1, color_mod: 0 }, Point::new(3, 2) ));
+    let berry = tile_manager.index_of("Berry")?;
+    let cola = tile_manager.index_of("Cola")?;
 
-    res
+    res.push((DrawSprite { idx: berry, color_mod: 0 }, Point::new(6, 6) ));
+    res.push((DrawSprite { idx: cola, color_mod: 0 }, Point::new(3, 2) ));
+
+    Ok(res)
 }
 
 impl SpriteMap {
-    pub fn new<F: Facade>(display: &F) -> Self {
-        let tile_manager = TileManager::from_config(display, "data/sprites.toml");
+    pub fn new<F: Facade>(display: &F) -> AtlasResult<Self> {
+        let tile_manager = TileManager::from_config(display, "data/sprites.toml")?;
 
         let vertices = glium::VertexBuffer::immutable(display, &QUAD).unwrap();
         let indices = glium::IndexBuffer::immutable(display, PrimitiveType::TrianglesList, &QUAD_INDICES).unwrap();
 
         let program = render::load_program(display, "sprite.vert", "sprite.frag").unwrap();
 
-        let sprites = make_map();
+        let sprites = make_map(&tile_manager)?;
 
-        SpriteMap {
+        Ok(SpriteMap {
             sprites: sprites,
             indices: indices,
             vertices: vertices,
             program: program,
             tile_manager: tile_manager,
-        }
+        })
     }
 
-    fn create_instances<F>(&self, display: &F, pass: usize, msecs: u64) -> glium::VertexBuffer<Instance>
+    /// Makes instances for the sprites on the given page that are on
+    /// screen. Sprites can be larger than a cell, so those just off screen
+    /// are kept as well.
+    fn create_instances<F>(&self, display: &F, viewport: &Viewport, pass: usize, msecs: u64) -> glium::VertexBuffer<Instance>
         where F: glium::backend::Facade {
 
         let data = self.sprites.iter()
+            .filter(|&&(_, c)| viewport.is_cell_visible(&c, 1))
             .filter(|&&(ref sprite, _)| {
-                let texture_idx = self.tile_manager.get_tile_texture_idx(sprite.idx);
+                let texture_idx = self.tile_manager.get_tile_texture_idx(sprite.idx, msecs);
                 texture_idx == pass
             })
             .map(|&(ref sprite, c)| {
                 let (x, y) = (c.x, c.y);
                 let (tx, ty) = self.tile_manager.get_texture_offset(sprite.idx, msecs);
                 let (sx, sy) = self.tile_manager.get_tile_texture_size(sprite.idx);
-                let tex_ratio = self.tile_manager.get_sprite_tex_ratio(sprite.idx);
+                let tex_ratio = self.tile_manager.get_sprite_tex_ratio(sprite.idx, msecs);
 
                 Instance { map_coord: [x as u32, y as u32],
                            tex_offset: [tx, ty],
@@ -96,14 +104,14 @@ impl<'a> Renderable for SpriteMap {
 
             let uniforms = uniform! {
                 matrix: proj,
-                tile_size: [48u32; 2],
+                tile_size: [viewport.tile_size.0, viewport.tile_size.1],
                 tex: texture.sampled()
                     .wrap_function(glium::uniforms::SamplerWrapFunction::Clamp)
                     .minify_filter(glium::uniforms::MinifySamplerFilter::Nearest)
                     .magnify_filter(glium::uniforms::MagnifySamplerFilter::Nearest),
             };
 
-            let instances = self.create_instances(display, pass, msecs);
+            let instances = self.create_instances(display, viewport, pass, msecs);
 
             let params = glium::DrawParameters {
                 blend: glium::Blend::alpha_blending(),
@@ -1,230 +1,660 @@
+use std::cmp;
+use std::collections::{HashMap, HashSet};
+use std::mem;
+use std::ops::Range;
+
 use glium;
 use glium::backend::Facade;
 use glium::index::PrimitiveType;
 
 use atlas::*;
-use board::Board;
-use point::Direction;
-use point::Point;
-use point;
-use render::{self, Renderable, Viewport, Vertex, QUAD, QUAD_INDICES};
+use autotile;
+use board::{chunk, Board, Layer, CHUNK_SIZE, LAYERS};
+use point::{Point, SquareIter};
+use render::{self, Renderable, RenderResult, Viewport, Vertex, QUAD, QUAD_INDICES};
+use terrain::TerrainRegistry;
 
 #[derive(Copy, Clone)]
 struct Instance {
     map_coord: [u32; 2],
     tex_offset: [f32; 2],
+    tex_ratio: [f32; 2],
     quadrant: i8,
-    autotile: i8,
-    autotile_index: i8,
 }
 
-implement_vertex!(Instance, map_coord, tex_offset, quadrant, autotile,
-                  autotile_index);
+implement_vertex!(Instance, map_coord, tex_offset, tex_ratio, quadrant);
+
+/// Fills the slot of a quadrant that isn't drawn on this atlas page, or of a
+/// cell with no tile. The shader collapses it to a point.
+const BLANK: Instance = Instance {
+    map_coord: [0, 0],
+    tex_offset: [0.0, 0.0],
+    tex_ratio: [0.0, 0.0],
+    quadrant: -1,
+};
+
+/// The number of cells in a chunk, each of which has four instance slots
+/// on every atlas page.
+const CHUNK_CELLS: usize = (CHUNK_SIZE * CHUNK_SIZE) as usize;
 
+#[derive(Clone, Debug, PartialEq)]
 struct DrawTile {
     idx: usize,
     edges: u8,
 }
 
-pub struct TileMap {
-    map: Vec<(DrawTile, Point)>,
+/// Gets what to draw in a cell of a layer, `None` if its terrain has no tile
+/// or one the atlas doesn't have.
+fn make_tile<P>(board: &Board, layer: Layer, pos: Point, tile_manager: &TileManager<P>) -> Option<DrawTile> {
+    let idx = match board.terrain_in(layer, &pos).tile {
+        Some(ref name) => match tile_manager.index_of(name) {
+            Ok(idx) => idx,
+            Err(_)  => return None,
+        },
+        None           => return None,
+    };
 
-    indices: glium::IndexBuffer<u16>,
-    vertices: glium::VertexBuffer<Vertex>,
-    program: glium::Program,
+    Some(DrawTile {
+        idx: idx,
+        edges: autotile::get_neighboring_edges(&board.layer(layer), pos, board.terrains().connections()),
+    })
+}
 
-    tile_manager: TileManager,
+/// Gets the first and last chunk with any cell on screen. Only these are
+/// drawn.
+pub fn visible_chunks(viewport: &Viewport) -> (Point, Point) {
+    let (first, last) = viewport.visible_cells();
+    (chunk::chunk_of(&first), chunk::chunk_of(&last))
 }
 
-fn dir_to_bit(dir: Direction) -> u8 {
-    match dir {
-        Direction::NE => 0,
-        Direction::N  => 1,
-        Direction::NW => 2,
-        Direction::E  => 3,
-        Direction::W  => 4,
-        Direction::SE => 5,
-        Direction::S  => 6,
-        Direction::SW => 7,
+/// Gets the cells to redraw when the cells in `changed` change, by chunk.
+/// These include their neighbors, as their edges depend on them.
+fn patched_cells(changed: &[Point]) -> HashMap<Point, Vec<Point>> {
+    let mut cells = HashSet::new();
+    for pos in changed.iter() {
+        cells.extend(SquareIter::new(*pos, 1));
+    }
+
+    let mut by_chunk = HashMap::new();
+    for pos in cells.into_iter() {
+        by_chunk.entry(chunk::chunk_of(&pos)).or_insert_with(Vec::new).push(pos);
     }
+    by_chunk
 }
 
-fn get_neighboring_edges(map: &Board, pos: Point) -> u8 {
-    let my_type = map.get(&pos);
+fn in_range(pos: &Point, range: &(Point, Point)) -> bool {
+    pos.x >= range.0.x && pos.y >= range.0.y && pos.x <= range.1.x && pos.y <= range.1.y
+}
+
+/// Makes the instances of the cells in `range` of a chunk layer on the given
+/// atlas page, four for each cell in quadrant order. Quadrants packed onto
+/// other pages, and cells without a tile, are left blank, so a cell's
+/// instances are always in the same place in the buffer.
+fn make_instances<P: AtlasPage>(origin: Point, cells: &[Option<DrawTile>], range: Range<usize>, pass: usize,
+                                tile_manager: &TileManager<P>, msecs: u64) -> Vec<Instance> {
+    let mut res = Vec::new();
+    for i in range {
+        let tile = match cells[i] {
+            Some(ref tile) => tile,
+            None           => {
+                res.extend_from_slice(&[BLANK; 4]);
+                continue;
+            },
+        };
+
+        let quadrant_cells = match tile_manager.get_autotile_layout(tile.idx) {
+            Some(layout) => autotile::get_quadrant_cells(layout, tile.edges),
+            None         => autotile::PLAIN_QUADRANT_CELLS,
+        };
+
+        let c = origin + (i as i32 % CHUNK_SIZE, i as i32 / CHUNK_SIZE);
+        for (quadrant, cell) in autotile::QUADRANTS.iter().zip(quadrant_cells.iter()) {
+            // The quadrants of one tile can be packed onto different pages.
+            if tile_manager.get_quadrant_texture_idx(tile.idx, *cell, msecs) != pass {
+                res.push(BLANK);
+                continue;
+            }
 
-    let mut res: u8 = 0;
-    for dir in point::DIRECTIONS.iter() {
-        let new_pos = pos + *dir;
-        let same_type = map.get(&new_pos) == my_type;
-        if same_type {
-            res |= 1 << dir_to_bit(*dir);
+            let (tx, ty) = tile_manager.get_quadrant_offset(tile.idx, *cell, msecs);
+            let tex_ratio = tile_manager.get_quadrant_tex_ratio(tile.idx, *cell, msecs);
+
+            res.push(Instance { map_coord: [c.x as u32, c.y as u32],
+                                tex_offset: [tx, ty],
+                                tex_ratio: tex_ratio,
+                                quadrant: *quadrant, });
         }
     }
     res
 }
 
-const QUAD_NW: i8 = 0;
-const QUAD_NE: i8 = 1;
-const QUAD_SW: i8 = 2;
-const QUAD_SE: i8 = 3;
+/// Gets the slots in a layer buffer of the cells in `range` on one atlas
+/// page.
+fn slot_range(range: &Range<usize>, pass: usize) -> Range<usize> {
+    let first = pass * CHUNK_CELLS;
+    (first + range.start) * 4..(first + range.end) * 4
+}
 
-use point::Direction::*;
+/// Sorts cell indices into runs of consecutive cells, which are written to
+/// a buffer at once.
+fn cell_runs(cells: &[usize]) -> Vec<Range<usize>> {
+    let mut cells = cells.to_vec();
+    cells.sort();
+    cells.dedup();
+
+    let mut runs: Vec<Range<usize>> = Vec::new();
+    for &cell in cells.iter() {
+        if let Some(run) = runs.last_mut() {
+            if run.end == cell {
+                run.end += 1;
+                continue;
+            }
+        }
+        runs.push(cell..cell + 1);
+    }
+    runs
+}
 
-fn get_autotile_index(edges: u8, quadrant: i8) -> i8 {
-    let is_connected = |dir: Direction| (edges & (1 << dir_to_bit(dir))) > 0;
+/// The tiles to draw in one chunk of the board, a slot for each cell of
+/// each layer, kept while the chunk is near the screen and patched in place
+/// when its cells change.
+struct ChunkTiles {
+    origin: Point,
+    layers: Vec<Vec<Option<DrawTile>>>,
 
-    if !is_connected(N) && !is_connected(W) && !is_connected(E) && !is_connected(S) {
-        let ret = match quadrant {
-            QUAD_NW => {
-                0
-            },
-            QUAD_NE => {
-                1
-            },
-            QUAD_SW => {
-                4
-            },
-            QUAD_SE => {
-                5
-            },
-            _ => -1,
-        };
-        return ret;
-    }
-
-    // The tiles are in order from the corner inside.
-    let lookup_idx = |horiz: Direction, vert: Direction, corner: Direction, tiles: [i8; 4], corner_piece: i8| {
-        if !is_connected(horiz) && !is_connected(vert) {
-            tiles[0]
-        } else if !is_connected(horiz) && is_connected(vert) {
-            tiles[1]
-        } else if is_connected(horiz) && !is_connected(vert) {
-            tiles[2]
-        } else {
-            if !is_connected(corner) {
-                corner_piece
-            } else {
-                tiles[3]
+    /// The animated tiles used in the chunk, and the frame each was on when
+    /// the buffers were last written.
+    animated: Vec<(usize, usize)>,
+
+    /// The instances of each layer, one run of `CHUNK_CELLS * 4` slots per
+    /// atlas page, made when the chunk is first drawn. Only the cells that
+    /// change are written afterwards. `None` where the layer is empty.
+    buffers: Vec<Option<glium::VertexBuffer<Instance>>>,
+
+    /// The cells patched since the buffers were last written.
+    patched: Vec<usize>,
+}
+
+impl ChunkTiles {
+    fn new<P: AtlasPage>(board: &Board, chunk: &Point, tile_manager: &TileManager<P>) -> Self {
+        let origin = chunk::chunk_origin(chunk);
+        let mut animated = Vec::new();
+
+        let layers = LAYERS.iter().map(|&layer| {
+            let mut cells = Vec::new();
+            for y in 0..CHUNK_SIZE {
+                for x in 0..CHUNK_SIZE {
+                    let pos = origin + (x, y);
+                    let tile = if board.in_bounds(&pos) {
+                        make_tile(board, layer, pos, tile_manager)
+                    } else {
+                        None
+                    };
+                    if let Some(ref tile) = tile {
+                        if tile_manager.is_animated(tile.idx) && !animated.iter().any(|&(idx, _)| idx == tile.idx) {
+                            animated.push((tile.idx, 0));
+                        }
+                    }
+                    cells.push(tile);
+                }
             }
+            cells
+        }).collect();
+
+        ChunkTiles {
+            origin: origin,
+            layers: layers,
+            animated: animated,
+            buffers: Vec::new(),
+            patched: Vec::new(),
         }
-    };
+    }
 
-    match quadrant {
-        QUAD_NW => {
-            lookup_idx(N, W, NW, [8, 9, 12, 13], 2)
-        },
-        QUAD_NE => {
-            lookup_idx(N, E, NE, [11, 10, 15, 14], 3)
-        },
-        QUAD_SW => {
-            lookup_idx(S, W, SW, [20, 21, 16, 17], 6)
-        },
-        QUAD_SE => {
-            lookup_idx(S, E, SE, [23, 22, 19, 18], 7)
-        },
-        _ => -1,
+    fn is_animated(&self) -> bool {
+        !self.animated.is_empty()
     }
-}
 
-fn make_map(map: &Board) -> Vec<(DrawTile, Point)> {
-    let mut res = Vec::new();
-    for i in 0..(map.width()) {
-        for j in 0..(map.height()) {
-            let pos = Point::new(i, j);
-            let tile = DrawTile {
-                idx: map.get(&pos).n(),
-                edges: get_neighboring_edges(map, pos),
-            };
-            res.push((tile, pos));
+    /// Checks if any animated tile in the chunk is on a different frame than
+    /// when its instances were made.
+    fn frame_changed<P: AtlasPage>(&self, tile_manager: &TileManager<P>, msecs: u64) -> bool {
+        self.animated.iter().any(|&(idx, frame)| tile_manager.get_frame(idx, msecs) != frame)
+    }
+
+    fn pos_of(&self, idx: usize) -> Point {
+        self.origin + (idx as i32 % CHUNK_SIZE, idx as i32 / CHUNK_SIZE)
+    }
+
+    /// Remakes the tiles of `cells` on every layer, which must all be in
+    /// this chunk. Their instances are written on the next `prepare`.
+    fn patch<P: AtlasPage>(&mut self, board: &Board, cells: &[Point], tile_manager: &TileManager<P>) {
+        for pos in cells.iter() {
+            let idx = ((pos.y - self.origin.y) * CHUNK_SIZE + pos.x - self.origin.x) as usize;
+            for &layer in LAYERS.iter() {
+                let tile = if board.in_bounds(pos) {
+                    make_tile(board, layer, *pos, tile_manager)
+                } else {
+                    None
+                };
+                if let Some(ref tile) = tile {
+                    if tile_manager.is_animated(tile.idx) && !self.animated.iter().any(|&(idx, _)| idx == tile.idx) {
+                        self.animated.push((tile.idx, 0));
+                    }
+                }
+                self.layers[layer.index()][idx] = tile;
+            }
+            self.patched.push(idx);
         }
+
+        // Forget animated tiles no cell is drawn with anymore, so frame
+        // changes stop rewriting the chunk for them.
+        let layers = &self.layers;
+        self.animated.retain(|&(animated, _)| {
+            layers.iter().any(|cells| cells.iter().any(|cell| cell.as_ref().map_or(false, |tile| tile.idx == animated)))
+        });
     }
-    res
+
+    /// Gets the cells of a layer drawn with animated tiles.
+    fn animated_cells<P: AtlasPage>(&self, layer: Layer, tile_manager: &TileManager<P>) -> Vec<usize> {
+        self.layers[layer.index()].iter()
+            .enumerate()
+            .filter(|&(_, cell)| cell.as_ref().map_or(false, |tile| tile_manager.is_animated(tile.idx)))
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    /// Makes the instances of every cell of a layer, for each atlas page in
+    /// turn.
+    fn layer_instances<P: AtlasPage>(&self, layer: Layer, tile_manager: &TileManager<P>, msecs: u64) -> Vec<Instance> {
+        let mut data = Vec::new();
+        for pass in 0..tile_manager.passes() {
+            data.extend(make_instances(self.origin, &self.layers[layer.index()], 0..CHUNK_CELLS,
+                                       pass, tile_manager, msecs));
+        }
+        data
+    }
+
+    /// Makes a buffer for every layer with any tiles in it.
+    fn make_buffers<F: Facade, P: AtlasPage>(&mut self, display: &F, tile_manager: &TileManager<P>, msecs: u64) {
+        let mut buffers = Vec::new();
+        for &layer in LAYERS.iter() {
+            if self.layers[layer.index()].iter().all(|cell| cell.is_none()) {
+                buffers.push(None);
+            } else {
+                let data = self.layer_instances(layer, tile_manager, msecs);
+                buffers.push(Some(glium::VertexBuffer::dynamic(display, &data).unwrap()));
+            }
+        }
+        self.buffers = buffers;
+    }
+
+    /// Writes the instances of `cells` of a layer over their slots in its
+    /// buffer, leaving the rest of the buffer alone. A layer that was empty
+    /// gets a buffer once a tile is put on it.
+    fn write_cells<F: Facade, P: AtlasPage>(&mut self, display: &F, layer: Layer, cells: &[usize],
+                                            tile_manager: &TileManager<P>, msecs: u64) {
+        if self.buffers[layer.index()].is_none() {
+            if cells.iter().any(|&i| self.layers[layer.index()][i].is_some()) {
+                let data = self.layer_instances(layer, tile_manager, msecs);
+                self.buffers[layer.index()] = Some(glium::VertexBuffer::dynamic(display, &data).unwrap());
+            }
+            return;
+        }
+
+        let buffer = match self.buffers[layer.index()] {
+            Some(ref mut buffer) => buffer,
+            None                 => return,
+        };
+
+        for run in cell_runs(cells).iter() {
+            for pass in 0..tile_manager.passes() {
+                let data = make_instances(self.origin, &self.layers[layer.index()], run.clone(),
+                                          pass, tile_manager, msecs);
+                buffer.slice_mut(slot_range(run, pass)).unwrap().write(&data);
+            }
+        }
+    }
+}
+
+pub struct TileMap {
+    /// The tiles of the chunks on or near the screen, by chunk position.
+    chunks: HashMap<Point, ChunkTiles>,
+
+    /// The first and last chunk on screen at the last update.
+    view: (Point, Point),
+    visible: Vec<bool>,
+
+    indices: glium::IndexBuffer<u16>,
+    vertices: glium::VertexBuffer<Vertex>,
+    program: glium::Program,
+
+    /// Stands in for the fog texture when nothing is shaded.
+    no_fog: glium::texture::Texture2d,
+
+    tile_manager: TileManager,
 }
 
 impl TileMap {
-    pub fn new<F: Facade>(display: &F) -> Self {
-        let tile_manager = TileManager::from_config(display, "data/tiles.toml");
+    pub fn new<F: Facade>(display: &F, terrains: &TerrainRegistry) -> RenderResult<Self> {
+        let tile_manager = TileManager::from_config(display, "data/tiles.toml")?;
+        terrains.check_tiles(&tile_manager)?;
 
         let vertices = glium::VertexBuffer::immutable(display, &QUAD).unwrap();
         let indices = glium::IndexBuffer::immutable(display, PrimitiveType::TrianglesList, &QUAD_INDICES).unwrap();
 
         let program = render::load_program(display, "tile.vert", "tile.frag").unwrap();
+        let no_fog = glium::texture::RawImage2d::from_raw_rgba(vec![0u8; 4], (1, 1));
+        let no_fog = glium::texture::Texture2d::new(display, no_fog).unwrap();
 
-        TileMap {
-            map: Vec::new(),
+        Ok(TileMap {
+            chunks: HashMap::new(),
+            view: (Point::new(0, 0), Point::new(-1, -1)),
+            visible: vec![true; LAYERS.len()],
             indices: indices,
             vertices: vertices,
             program: program,
+            no_fog: no_fog,
             tile_manager: tile_manager,
-        }
+        })
     }
 
-    pub fn update(&mut self, board: &Board) {
-        self.map = make_map(board);
+    /// Gets the size in pixels of a board cell on screen.
+    pub fn tile_size(&self) -> (u32, u32) {
+        self.tile_manager.grid_size()
     }
 
-    fn create_instances<F>(&self, display: &F, pass: usize, msecs: u64) -> glium::VertexBuffer<Instance>
-        where F: glium::backend::Facade {
+    /// Gets the settings the tile atlas was packed with.
+    pub fn packing(&self) -> PackingSettings {
+        self.tile_manager.packing()
+    }
 
-        let data = self.map.iter()
-            .filter(|&&(ref tile, _)| {
-                let texture_idx = self.tile_manager.get_tile_texture_idx(tile.idx);
-                texture_idx == pass
-            })
-            .flat_map(|&(ref tile, c)| {
-                let mut res = Vec::new();
-                for quadrant in 0..4 {
-                    let (x, y) = (c.x, c.y);
-                    let (tx, ty) = self.tile_manager.get_texture_offset(tile.idx, msecs);
+    /// Rebuilds the chunks of `board` on screen.
+    pub fn update(&mut self, board: &Board, viewport: &Viewport) {
+        self.chunks.clear();
+        self.patch(board, &[], viewport);
+    }
 
-                    let autotile_index = get_autotile_index(tile.edges, quadrant);
+    /// Redraws the cells in `changed`, as returned by `Board::take_dirty`,
+    /// and their neighbors in the chunks that are kept. Chunks that came on
+    /// screen since the last update are built, and ones far off screen
+    /// dropped.
+    pub fn patch(&mut self, board: &Board, changed: &[Point], viewport: &Viewport) {
+        for (pos, cells) in patched_cells(changed).iter() {
+            if let Some(chunk) = self.chunks.get_mut(pos) {
+                chunk.patch(board, cells, &self.tile_manager);
+            }
+        }
 
-                    res.push(Instance { map_coord: [x as u32, y as u32],
-                                        tex_offset: [tx, ty],
-                                        quadrant: quadrant,
-                                        autotile: 1,
-                                        autotile_index: autotile_index, });
+        // Keep a chunk's worth of margin, so going back and forth over a
+        // chunk border doesn't build the same chunks over and over.
+        let view = visible_chunks(viewport);
+        let keep = (view.0 - (1, 1), view.1 + (1, 1));
+        let far: Vec<Point> = self.chunks.keys().filter(|c| !in_range(*c, &keep)).cloned().collect();
+        for chunk in far.iter() {
+            self.chunks.remove(chunk);
+        }
+
+        let last = chunk::chunk_of(&Point::new(board.width() - 1, board.height() - 1));
+        for y in cmp::max(view.0.y, 0)..(cmp::min(view.1.y, last.y) + 1) {
+            for x in cmp::max(view.0.x, 0)..(cmp::min(view.1.x, last.x) + 1) {
+                let pos = Point::new(x, y);
+                if !self.chunks.contains_key(&pos) {
+                    let tiles = ChunkTiles::new(board, &pos, &self.tile_manager);
+                    self.chunks.insert(pos, tiles);
                 }
-                res
-            }).collect::<Vec<Instance>>();
+            }
+        }
 
-        glium::VertexBuffer::dynamic(display, &data).unwrap()
+        self.view = view;
+    }
+
+    /// Makes the instance buffers of chunks on screen that don't have any
+    /// yet, and rewrites the patched cells of the others and the animated
+    /// cells of those whose animations moved on a frame. Call before
+    /// rendering.
+    pub fn prepare<F: Facade>(&mut self, display: &F, msecs: u64) {
+        let tile_manager = &self.tile_manager;
+        let view = self.view;
+
+        for (pos, chunk) in self.chunks.iter_mut() {
+            if !in_range(pos, &view) {
+                continue;
+            }
+
+            if chunk.buffers.is_empty() {
+                chunk.make_buffers(display, tile_manager, msecs);
+            } else {
+                let patched = mem::replace(&mut chunk.patched, Vec::new());
+                let frame_changed = chunk.frame_changed(tile_manager, msecs);
+                for &layer in LAYERS.iter() {
+                    let mut cells = patched.clone();
+                    if frame_changed {
+                        cells.extend(chunk.animated_cells(layer, tile_manager));
+                    }
+                    chunk.write_cells(display, layer, &cells, tile_manager, msecs);
+                }
+            }
+
+            chunk.patched.clear();
+            for entry in chunk.animated.iter_mut() {
+                entry.1 = tile_manager.get_frame(entry.0, msecs);
+            }
+        }
+    }
+
+    pub fn set_layer_visible(&mut self, layer: Layer, visible: bool) {
+        self.visible[layer.index()] = visible;
+    }
+
+    pub fn is_layer_visible(&self, layer: Layer) -> bool {
+        self.visible[layer.index()]
+    }
+
+    /// Draws the tiles, washing out the colors of each cell by the red
+    /// channel of the matching pixel of `fog`, as made by `ShadowMap`, whose
+    /// first pixel is for the cell given with it.
+    pub fn render_with_fog<S>(&self, target: &mut S, viewport: &Viewport,
+                              fog: Option<(&glium::texture::Texture2d, Point)>)
+        where S: glium::Surface {
+
+        let (fog, fog_origin) = fog.unwrap_or((&self.no_fog, Point::new(0, 0)));
+        let (proj, scissor) = viewport.main_window();
+        let passes = self.tile_manager.passes();
+
+        // Every pass of a layer is drawn before the next layer, so upper
+        // layers cover lower ones whichever page their tiles are on.
+        for &layer in LAYERS.iter() {
+            if !self.is_layer_visible(layer) {
+                continue;
+            }
+
+            for pass in 0..passes {
+                let texture = self.tile_manager.get_texture(pass);
+
+                let uniforms = uniform! {
+                    matrix: proj,
+                    tile_size: [viewport.tile_size.0, viewport.tile_size.1],
+                    tex: texture.sampled()
+                        .wrap_function(glium::uniforms::SamplerWrapFunction::Clamp)
+                        .minify_filter(glium::uniforms::MinifySamplerFilter::Nearest)
+                        .magnify_filter(glium::uniforms::MagnifySamplerFilter::Nearest),
+                    fog: fog,
+                    fog_origin: [fog_origin.x, fog_origin.y],
+                };
+
+                let params = glium::DrawParameters {
+                    blend: glium::Blend::alpha_blending(),
+                    scissor: Some(scissor),
+                    .. Default::default()
+                };
+
+                for (pos, chunk) in self.chunks.iter() {
+                    if !in_range(pos, &self.view) {
+                        continue;
+                    }
+
+                    let instances = match chunk.buffers.get(layer.index()) {
+                        Some(&Some(ref buffer)) => buffer.slice(slot_range(&(0..CHUNK_CELLS), pass)).unwrap(),
+                        _                       => continue,
+                    };
+
+                    target.draw((&self.vertices, instances.per_instance().unwrap()),
+                                &self.indices,
+                                &self.program,
+                                &uniforms,
+                                &params).unwrap();
+                }
+            }
+        }
     }
 }
 
-impl<'a> Renderable for TileMap {
-    fn render<F, S>(&self, display: &F, target: &mut S, viewport: &Viewport, msecs: u64)
+impl Renderable for TileMap {
+    fn render<F, S>(&self, _display: &F, target: &mut S, viewport: &Viewport, _msecs: u64)
         where F: glium::backend::Facade, S: glium::Surface {
+        self.render_with_fog(target, viewport, None);
+    }
+}
 
-        let (proj, scissor) = viewport.main_window();
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::*;
+    use testing;
+    use terrain::TerrainRegistry;
+
+    #[test]
+    fn test_chunk_tiles() {
+        let tiles = testing::tile_images();
+        let terrains = Rc::new(TerrainRegistry::from_toml("data/terrain.toml").unwrap());
+        let wall = terrains.find("Wall").unwrap();
+        let floor = terrains.find("Floor").unwrap();
+
+        let mut board = Board::new(terrains, CHUNK_SIZE + 6, 5, wall);
+        let chunk = ChunkTiles::new(&board, &Point::new(1, 0), tiles);
+        assert_eq!(chunk.origin, Point::new(CHUNK_SIZE, 0));
+        assert!(!chunk.is_animated());
+
+        let ground = &chunk.layers[Layer::Ground.index()];
+        assert!(ground[0].is_some());
+        // Past the edge of the board.
+        assert!(ground[6].is_none());
+        assert!(ground[(5 * CHUNK_SIZE) as usize].is_none());
+        assert!(chunk.layers[Layer::Roof.index()].iter().all(|cell| cell.is_none()));
+        assert_eq!(chunk.pos_of(CHUNK_SIZE as usize + 2), Point::new(CHUNK_SIZE + 2, 1));
+
+        // Floor is drawn with water, which is animated.
+        let pos = Point::new(CHUNK_SIZE + 1, 1);
+        board.set(&pos, floor).unwrap();
+        let mut chunk = ChunkTiles::new(&board, &Point::new(1, 0), tiles);
+        assert_eq!(chunk.animated, vec![(tiles.expect_index_of("Water"), 0)]);
+
+        // Patching the water back to wall leaves nothing animated.
+        board.set(&pos, wall).unwrap();
+        chunk.patch(&board, &[pos], tiles);
+        assert!(chunk.animated.is_empty());
+        assert!(!chunk.is_animated());
+    }
+
+    #[test]
+    fn test_instance_slots() {
+        let tiles = testing::tile_images();
+        let terrains = Rc::new(TerrainRegistry::from_toml("data/terrain.toml").unwrap());
+        let wall = terrains.find("Wall").unwrap();
+        let floor = terrains.find("Floor").unwrap();
+
+        let mut board = Board::new(terrains, 4, 4, wall);
+        board.set(&Point::new(1, 1), floor).unwrap();
+        let chunk = ChunkTiles::new(&board, &Point::new(0, 0), tiles);
+
+        // Four slots for every cell of the chunk on each page, and a quadrant
+        // drawn for each cell on the board.
+        let data = chunk.layer_instances(Layer::Ground, tiles, 0);
+        assert_eq!(data.len(), tiles.passes() * CHUNK_CELLS * 4);
+        assert_eq!(data.iter().filter(|i| i.quadrant >= 0).count(), 16 * 4);
+
+        let cell = CHUNK_SIZE as usize + 1;
+        let drawn = (0..tiles.passes())
+            .flat_map(|pass| make_instances(chunk.origin, &chunk.layers[0], cell..cell + 1, pass, tiles, 0))
+            .filter(|i| i.quadrant >= 0)
+            .count();
+        assert_eq!(drawn, 4);
+        assert_eq!(slot_range(&(cell..cell + 1), 1), (CHUNK_CELLS + cell) * 4..(CHUNK_CELLS + cell + 1) * 4);
+
+        // The one floor cell is animated.
+        assert_eq!(chunk.animated_cells(Layer::Ground, tiles), vec![cell]);
+        assert!(!chunk.frame_changed(tiles, 0));
+        let water = tiles.expect_index_of("Water");
+        let next = (1..100000).find(|&msecs| tiles.get_frame(water, msecs) != 0).unwrap();
+        assert!(chunk.frame_changed(tiles, next));
+    }
+
+    #[test]
+    fn test_cell_runs() {
+        assert_eq!(cell_runs(&[]), vec![]);
+        assert_eq!(cell_runs(&[7, 3, 4, 5, 9, 8, 3]), vec![3..6, 7..10]);
+    }
+
+    #[test]
+    fn test_patched_cells() {
+        let patched = patched_cells(&[Point::new(5, 5), Point::new(6, 5)]);
+        assert_eq!(patched.len(), 1);
+        assert_eq!(patched[&Point::new(0, 0)].len(), 12);
+
+        // A corner cell also changes the edges of cells in the three chunks
+        // touching it.
+        let patched = patched_cells(&[Point::new(CHUNK_SIZE - 1, CHUNK_SIZE - 1)]);
+        assert_eq!(patched.len(), 4);
+        assert_eq!(patched[&Point::new(0, 0)].len(), 4);
+        assert_eq!(patched[&Point::new(1, 1)], vec![Point::new(CHUNK_SIZE, CHUNK_SIZE)]);
+    }
+
+    #[test]
+    fn test_patch_matches_rebuild() {
+        let tiles = testing::tile_images();
+        let terrains = Rc::new(TerrainRegistry::from_toml("data/terrain.toml").unwrap());
+        let wall = terrains.find("Wall").unwrap();
+        let floor = terrains.find("Floor").unwrap();
+
+        let mut board = Board::new(terrains, CHUNK_SIZE + 6, 5, wall);
+        let chunks = [Point::new(0, 0), Point::new(1, 0)];
+        let mut patched: Vec<ChunkTiles> = chunks.iter().map(|c| ChunkTiles::new(&board, c, tiles)).collect();
+        board.take_dirty();
+
+        board.set(&Point::new(2, 2), floor).unwrap();
+        board.set(&Point::new(CHUNK_SIZE - 1, 0), floor).unwrap();
+        board.set(&Point::new(CHUNK_SIZE + 5, 4), floor).unwrap();
+        let changed = board.take_dirty();
+        for (pos, cells) in patched_cells(&changed).iter() {
+            if let Some(i) = chunks.iter().position(|c| c == pos) {
+                patched[i].patch(&board, cells, tiles);
+            }
+        }
 
-        for pass in 0..self.tile_manager.passes() {
-            let texture = self.tile_manager.get_texture(pass);
-            let tex_ratio = self.tile_manager.get_tilemap_tex_ratio(pass);
-
-            let uniforms = uniform! {
-                matrix: proj,
-                tile_size: [48u32; 2],
-                tex: texture.sampled()
-                    .wrap_function(glium::uniforms::SamplerWrapFunction::Clamp)
-                    .minify_filter(glium::uniforms::MinifySamplerFilter::Nearest)
-                    .magnify_filter(glium::uniforms::MagnifySamplerFilter::Nearest),
-                tex_ratio: tex_ratio,
-            };
-
-            let instances = self.create_instances(display, pass, msecs);
-
-            let params = glium::DrawParameters {
-                blend: glium::Blend::alpha_blending(),
-                scissor: Some(scissor),
-                .. Default::default()
-            };
-
-            target.draw((&self.vertices, instances.per_instance().unwrap()),
-                        &self.indices,
-                        &self.program,
-                        &uniforms,
-                        &params).unwrap();
+        for (chunk, pos) in patched.iter().zip(chunks.iter()) {
+            let rebuilt = ChunkTiles::new(&board, pos, tiles);
+            assert_eq!(chunk.layers, rebuilt.layers);
+            assert_eq!(chunk.animated, rebuilt.animated);
         }
+        // Every changed cell and its neighbors in the chunk are patched, even
+        // those off the board.
+        assert_eq!(patched[0].patched.len(), 9 + 4);
+        assert_eq!(patched[1].patched.len(), 2 + 9);
+    }
+
+    #[test]
+    fn test_visible_chunks() {
+        let tile = 16;
+        let chunk = tile * CHUNK_SIZE as u32;
+        let mut viewport = Viewport {
+            position: (0, 0),
+            size: (chunk, chunk + 1),
+            scale: 1.0,
+            camera: (0, 0),
+            tile_size: (tile, tile),
+        };
+        assert_eq!(visible_chunks(&viewport), (Point::new(0, 0), Point::new(0, 1)));
+
+        viewport.camera = (-1, chunk as i32);
+        assert_eq!(visible_chunks(&viewport), (Point::new(-1, 1), Point::new(0, 2)));
     }
 }
@@ -1,17 +1,48 @@
 use cgmath;
 use glium;
 
+use point::Point;
+
 #[derive(Debug)]
 pub struct Viewport {
     pub position: (u32, u32),
     pub size: (u32, u32),
     pub scale: f32,
     pub camera: (i32, i32),
+
+    /// Size in pixels of a board cell on screen.
+    pub tile_size: (u32, u32),
 }
 
 pub type RendererSubarea = ([[f32; 4]; 4], glium::Rect);
 
+/// Gets the first and last cell at least partly inside the part of the world
+/// on screen, which is `size` pixels with its top left corner at `camera`,
+/// on a grid of cells `tile_size` pixels across. Anything outside these can
+/// be left undrawn.
+pub fn visible_cells(camera: (i32, i32), size: (u32, u32), tile_size: (u32, u32)) -> (Point, Point) {
+    let (tw, th) = (tile_size.0 as f32, tile_size.1 as f32);
+    let (x, y) = (camera.0 as f32, camera.1 as f32);
+    let (w, h) = (size.0 as f32, size.1 as f32);
+
+    (Point::new((x / tw).floor() as i32, (y / th).floor() as i32),
+     Point::new(((x + w) / tw).ceil() as i32 - 1, ((y + h) / th).ceil() as i32 - 1))
+}
+
 impl Viewport {
+    /// Gets the first and last cell on screen, as with `visible_cells`.
+    pub fn visible_cells(&self) -> (Point, Point) {
+        visible_cells(self.camera, self.size, self.tile_size)
+    }
+
+    /// Checks if the cell at `pos` is on screen, or within `margin` cells of
+    /// it.
+    pub fn is_cell_visible(&self, pos: &Point, margin: i32) -> bool {
+        let (first, last) = self.visible_cells();
+        pos.x >= first.x - margin && pos.y >= first.y - margin &&
+            pos.x <= last.x + margin && pos.y <= last.y + margin
+    }
+
     pub fn main_window(&self) -> RendererSubarea {
         let (w, h) = self.scaled_size();
         self.make_subarea((0, 0, w, h - 120))
@@ -57,3 +88,39 @@ impl Viewport {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_visible_cells() {
+        assert_eq!(visible_cells((0, 0), (96, 48), (48, 48)), (Point::new(0, 0), Point::new(1, 0)));
+
+        // Cells only partly on screen are visible.
+        assert_eq!(visible_cells((24, 47), (96, 48), (48, 48)), (Point::new(0, 0), Point::new(2, 1)));
+    }
+
+    #[test]
+    fn test_visible_cells_left_of_origin() {
+        assert_eq!(visible_cells((-1, -48), (48, 48), (48, 48)), (Point::new(-1, -1), Point::new(0, -1)));
+        assert_eq!(visible_cells((-96, 0), (48, 10), (48, 48)), (Point::new(-2, 0), Point::new(-2, 0)));
+    }
+
+    #[test]
+    fn test_is_cell_visible() {
+        let viewport = Viewport {
+            position: (0, 0),
+            size: (96, 96),
+            scale: 1.0,
+            camera: (48, 0),
+            tile_size: (48, 48),
+        };
+
+        assert!(viewport.is_cell_visible(&Point::new(1, 1), 0));
+        assert!(viewport.is_cell_visible(&Point::new(2, 0), 0));
+        assert!(!viewport.is_cell_visible(&Point::new(0, 0), 0));
+        assert!(!viewport.is_cell_visible(&Point::new(3, 0), 0));
+        assert!(viewport.is_cell_visible(&Point::new(3, 0), 1));
+    }
+}
@@ -1,41 +1,506 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use serde::de::Deserialize;
+use toml::Value;
+
+use atlas::TileManager;
+use autotile::ConnectionRules;
+use util;
+use util::toml::TomlError;
+
+#[derive(Eq, PartialEq, Copy, Clone, Debug)]
+pub enum DoorState {
+    Closed,
+    Open,
+}
+
 #[derive(Eq, PartialEq, Clone, Debug)]
 pub enum Feature {
-    Door,
+    Door(DoorState),
     UpStair,
     DownStair,
 }
 
-#[derive(Eq, PartialEq, Copy, Clone, Debug)]
-pub enum Terrain {
-    Wall,
-    Floor,
-    Important,
-    Nothing,
+impl Feature {
+    /// Gets the name the feature is saved under in board files, which is
+    /// also the key of its tile in the `[features]` table of `terrain.toml`.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Feature::Door(DoorState::Closed) => "door",
+            Feature::Door(DoorState::Open)   => "open_door",
+            Feature::UpStair                 => "up_stair",
+            Feature::DownStair               => "down_stair",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Feature> {
+        match name {
+            "door"       => Some(Feature::Door(DoorState::Closed)),
+            "open_door"  => Some(Feature::Door(DoorState::Open)),
+            "up_stair"   => Some(Feature::UpStair),
+            "down_stair" => Some(Feature::DownStair),
+            _            => None,
+        }
+    }
 }
 
-impl Terrain {
-    pub fn to_char(&self) -> char {
+/// Everything that can go wrong while loading a terrain config.
+#[derive(Debug)]
+pub enum TerrainError {
+    /// The file couldn't be read.
+    Io(String, io::Error),
+
+    /// The file isn't valid TOML, or a value is missing or has the wrong type.
+    Config(String, TomlError),
+
+    /// A terrain glyph isn't a single character.
+    Glyph(String, String),
+
+    /// Two terrain types have the same name.
+    DuplicateTerrain(String, String),
+
+    /// A group's `connects_to` names a group that is neither declared nor
+    /// used by any terrain.
+    UnknownGroup(String, String, String),
+
+    /// The `[features]` table has a feature that doesn't exist.
+    UnknownFeature(String, String),
+
+    /// A terrain's tile isn't in the tile atlas it is drawn with.
+    UnknownTile(String, String),
+
+    /// A feature's tile isn't in the feature atlas.
+    UnknownFeatureTile(String, String),
+}
+
+pub type TerrainResult<T> = Result<T, TerrainError>;
+
+impl fmt::Display for TerrainError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
-            Terrain::Wall => '#',
-            Terrain::Floor => '.',
-            Terrain::Important => '$',
-            Terrain::Nothing => ' ',
+            TerrainError::Io(ref path, ref e) => write!(f, "{}: {}", path, e),
+            TerrainError::Config(ref path, ref e) => write!(f, "{}: invalid terrain config: {}", path, e),
+            TerrainError::Glyph(ref path, ref glyph) =>
+                write!(f, "{}: terrain glyph \"{}\" must be a single character", path, glyph),
+            TerrainError::DuplicateTerrain(ref path, ref name) => write!(f, "{}: duplicate terrain \"{}\"", path, name),
+            TerrainError::UnknownGroup(ref path, ref group, ref other) =>
+                write!(f, "{}: group \"{}\" connects to unknown group \"{}\"", path, group, other),
+            TerrainError::UnknownFeature(ref path, ref name) => write!(f, "{}: unknown feature \"{}\"", path, name),
+            TerrainError::UnknownTile(ref terrain, ref tile) =>
+                write!(f, "terrain \"{}\" is drawn with tile \"{}\", which isn't in the tile atlas", terrain, tile),
+            TerrainError::UnknownFeatureTile(ref feature, ref tile) =>
+                write!(f, "feature \"{}\" is drawn with tile \"{}\", which isn't in the feature atlas", feature, tile),
         }
     }
+}
 
-    pub fn n(&self) -> usize {
+impl Error for TerrainError {
+    fn description(&self) -> &str {
         match *self {
-            Terrain::Wall => 0,
-            Terrain::Floor => 1,
-            Terrain::Important => 0,
-            Terrain::Nothing => 0,
+            TerrainError::Io(..)                 => "I/O error",
+            TerrainError::Config(..)             => "invalid terrain config",
+            TerrainError::Glyph(..)              => "invalid terrain glyph",
+            TerrainError::DuplicateTerrain(..)   => "duplicate terrain",
+            TerrainError::UnknownGroup(..)       => "unknown group",
+            TerrainError::UnknownFeature(..)     => "unknown feature",
+            TerrainError::UnknownTile(..)        => "unknown tile",
+            TerrainError::UnknownFeatureTile(..) => "unknown feature tile",
         }
     }
 
-    pub fn is_blocking(&self) -> bool {
+    fn cause(&self) -> Option<&Error> {
         match *self {
-            Terrain::Wall | Terrain::Nothing => true,
-            _             => false,
+            TerrainError::Io(_, ref e) => Some(e),
+            _                          => None,
+        }
+    }
+}
+
+/// Compact handle to a terrain type defined in a `TerrainRegistry`.
+#[derive(Eq, PartialEq, Copy, Clone, Debug, Hash)]
+pub struct TerrainId(u16);
+
+/// The terrain outside the board and in unfilled cells. Always present in a
+/// registry, draws nothing and blocks movement.
+pub const TERRAIN_NOTHING: TerrainId = TerrainId(0);
+
+#[derive(Clone, Debug)]
+pub struct TerrainDef {
+    pub name: String,
+    pub glyph: char,
+
+    /// Name of the tile in `tiles.toml` to draw, if any.
+    pub tile: Option<String>,
+    pub blocking: bool,
+
+    /// Name of the connection group used when autotiling.
+    pub group: Option<String>,
+}
+
+/// All the terrain types a board can hold, loaded from a TOML file.
+pub struct TerrainRegistry {
+    defs: Vec<TerrainDef>,
+    by_name: HashMap<String, TerrainId>,
+    connections: ConnectionRules<TerrainId>,
+
+    /// Name of the tile in `features.toml` to draw for each feature, by
+    /// feature name.
+    feature_tiles: HashMap<String, String>,
+}
+
+impl TerrainRegistry {
+    pub fn new() -> Self {
+        let nothing = TerrainDef {
+            name: "Nothing".to_string(),
+            glyph: ' ',
+            tile: None,
+            blocking: true,
+            group: None,
+        };
+
+        let mut by_name = HashMap::new();
+        by_name.insert(nothing.name.clone(), TERRAIN_NOTHING);
+
+        TerrainRegistry {
+            defs: vec![nothing],
+            by_name: by_name,
+            connections: ConnectionRules::new(),
+            feature_tiles: HashMap::new(),
+        }
+    }
+
+    /// Loads the `[[terrain]]` and `[[groups]]` arrays and the `[features]`
+    /// table of a TOML file.
+    pub fn from_toml(filename: &str) -> TerrainResult<Self> {
+        let toml_str = util::toml::toml_string_from_file(filename)
+            .map_err(|e| TerrainError::Io(filename.to_string(), e))?;
+        TerrainRegistry::from_toml_str(filename, &toml_str)
+    }
+
+    fn from_toml_str(filename: &str, toml_str: &str) -> TerrainResult<Self> {
+        let val = util::toml::toml_value_from_string(toml_str)
+            .map_err(|e| TerrainError::Config(filename.to_string(), e))?;
+
+        let mut registry = TerrainRegistry::new();
+
+        for terrain in get_array(filename, &val, "terrain")?.iter() {
+            let glyph: String = expect(filename, terrain, "glyph")?;
+            let mut chars = glyph.chars();
+            let glyph = match (chars.next(), chars.next()) {
+                (Some(ch), None) => ch,
+                _                => return Err(TerrainError::Glyph(filename.to_string(), glyph.clone())),
+            };
+
+            let def = TerrainDef {
+                name: expect(filename, terrain, "name")?,
+                glyph: glyph,
+                tile: get_optional(filename, terrain, "tile")?,
+                blocking: get_optional(filename, terrain, "blocking")?.unwrap_or(false),
+                group: get_optional(filename, terrain, "group")?,
+            };
+
+            if registry.find(&def.name).is_some() {
+                return Err(TerrainError::DuplicateTerrain(filename.to_string(), def.name));
+            }
+            registry.add(def);
+        }
+
+        let mut groups = Vec::new();
+        for group in get_array(filename, &val, "groups")?.iter() {
+            let name: String = expect(filename, group, "name")?;
+            let connects_to: Vec<String> = get_optional(filename, group, "connects_to")?.unwrap_or(Vec::new());
+            let out_of_bounds = get_optional(filename, group, "out_of_bounds")?.unwrap_or(false);
+            groups.push((name, connects_to, out_of_bounds));
+        }
+
+        // A group exists if it's declared or has members, so check the names
+        // in `connects_to` only once every group is known.
+        for &(ref name, ref connects_to, _) in groups.iter() {
+            for other in connects_to.iter() {
+                let declared = groups.iter().any(|&(ref n, _, _)| n == other);
+                let has_members = registry.defs.iter().any(|def| def.group.as_ref() == Some(other));
+                if !declared && !has_members {
+                    return Err(TerrainError::UnknownGroup(filename.to_string(), name.clone(), other.clone()));
+                }
+            }
+        }
+
+        for (name, connects_to, out_of_bounds) in groups.into_iter() {
+            registry.connections.add_group(&name, Vec::new(), connects_to, out_of_bounds);
+        }
+
+        let features: HashMap<String, String> = get_optional(filename, &val, "features")?.unwrap_or(HashMap::new());
+        for (name, tile) in features.into_iter() {
+            match Feature::from_name(&name) {
+                Some(feature) => registry.set_feature_tile(&feature, &tile),
+                None          => return Err(TerrainError::UnknownFeature(filename.to_string(), name)),
+            }
+        }
+
+        Ok(registry)
+    }
+
+    /// Checks that every terrain with a tile is drawn with one `tiles` has,
+    /// so a misspelled tile name is found before anything is drawn.
+    pub fn check_tiles<P>(&self, tiles: &TileManager<P>) -> TerrainResult<()> {
+        for def in self.defs.iter() {
+            if let Some(ref tile) = def.tile {
+                if tiles.index_of(tile).is_err() {
+                    return Err(TerrainError::UnknownTile(def.name.clone(), tile.clone()));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that every feature in the `[features]` table is drawn with a
+    /// tile `tiles` has, whether or not any board has that feature.
+    pub fn check_feature_tiles<P>(&self, tiles: &TileManager<P>) -> TerrainResult<()> {
+        for (feature, tile) in self.feature_tiles.iter() {
+            if tiles.index_of(tile).is_err() {
+                return Err(TerrainError::UnknownFeatureTile(feature.clone(), tile.clone()));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn add(&mut self, def: TerrainDef) -> TerrainId {
+        assert!(!self.by_name.contains_key(&def.name), "Duplicate terrain \"{}\"", def.name);
+
+        let id = TerrainId(self.defs.len() as u16);
+        if let Some(ref group) = def.group {
+            self.connections.add_member(group, id);
+        }
+        self.by_name.insert(def.name.clone(), id);
+        self.defs.push(def);
+        id
+    }
+
+    pub fn get(&self, id: TerrainId) -> &TerrainDef {
+        &self.defs[id.0 as usize]
+    }
+
+    pub fn find(&self, name: &str) -> Option<TerrainId> {
+        self.by_name.get(name).cloned()
+    }
+
+    pub fn find_by_glyph(&self, glyph: char) -> Option<TerrainId> {
+        self.defs.iter().position(|def| def.glyph == glyph).map(|idx| TerrainId(idx as u16))
+    }
+
+    /// Finds the first terrain drawn with the tile of this name in
+    /// `tiles.toml`.
+    pub fn find_by_tile(&self, tile: &str) -> Option<TerrainId> {
+        self.defs.iter()
+            .position(|def| def.tile.as_ref().map_or(false, |t| t == tile))
+            .map(|idx| TerrainId(idx as u16))
+    }
+
+    pub fn connections(&self) -> &ConnectionRules<TerrainId> {
+        &self.connections
+    }
+
+    /// Draws `feature` with the tile of this name in `features.toml`.
+    pub fn set_feature_tile(&mut self, feature: &Feature, tile: &str) {
+        self.feature_tiles.insert(feature.name().to_string(), tile.to_string());
+    }
+
+    /// Gets the tile to draw for `feature`, if it's drawn at all.
+    pub fn feature_tile(&self, feature: &Feature) -> Option<&str> {
+        self.feature_tiles.get(feature.name()).map(|tile| tile.as_str())
+    }
+}
+
+fn get_array(filename: &str, val: &Value, key: &str) -> TerrainResult<Vec<Value>> {
+    match util::toml::get_value_in_table(val, key) {
+        Some(&Value::Array(ref array)) => Ok(array.clone()),
+        Some(_)                        => Err(TerrainError::Config(filename.to_string(), TomlError::InvalidValue(key.to_string()))),
+        None                           => Ok(Vec::new()),
+    }
+}
+
+fn get_optional<T: Deserialize>(filename: &str, val: &Value, key: &str) -> TerrainResult<Option<T>> {
+    util::toml::get_optional_value_in_table(val, key).map_err(|e| TerrainError::Config(filename.to_string(), e))
+}
+
+fn expect<T: Deserialize>(filename: &str, val: &Value, key: &str) -> TerrainResult<T> {
+    util::toml::expect_value_in_table(val, key).map_err(|e| TerrainError::Config(filename.to_string(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use testing::tile_images;
+
+    fn def(name: &str, glyph: char) -> TerrainDef {
+        TerrainDef {
+            name: name.to_string(),
+            glyph: glyph,
+            tile: None,
+            blocking: false,
+            group: None,
+        }
+    }
+
+    #[test]
+    fn test_nothing_is_builtin() {
+        let registry = TerrainRegistry::new();
+        assert_eq!(registry.find("Nothing"), Some(TERRAIN_NOTHING));
+        assert_eq!(registry.find_by_glyph(' '), Some(TERRAIN_NOTHING));
+        assert!(registry.get(TERRAIN_NOTHING).blocking);
+    }
+
+    #[test]
+    fn test_groups_connect() {
+        let mut registry = TerrainRegistry::new();
+        let mut floor = def("Floor", '.');
+        floor.group = Some("floor".to_string());
+        let mut carpet = def("Carpet", '=');
+        carpet.group = Some("floor".to_string());
+
+        let floor = registry.add(floor);
+        let carpet = registry.add(carpet);
+        let wall = registry.add(def("Wall", '#'));
+
+        assert!(registry.connections().connects(&floor, Some(&carpet)));
+        assert!(!registry.connections().connects(&floor, Some(&wall)));
+    }
+
+    #[test]
+    fn test_add() {
+        let mut registry = TerrainRegistry::new();
+        let mut wall = def("Wall", '#');
+        wall.tile = Some("StoneBrick".to_string());
+        let wall = registry.add(wall);
+        let floor = registry.add(def("Floor", '.'));
+
+        assert!(wall != floor);
+        assert_eq!(registry.find("Floor"), Some(floor));
+        assert_eq!(registry.find_by_glyph('#'), Some(wall));
+        assert_eq!(registry.get(wall).name, "Wall");
+        assert_eq!(registry.find("Lava"), None);
+        assert_eq!(registry.find_by_tile("StoneBrick"), Some(wall));
+        assert_eq!(registry.find_by_tile("Water"), None);
+    }
+
+    #[test]
+    fn test_feature_tiles() {
+        let mut registry = TerrainRegistry::new();
+        registry.set_feature_tile(&Feature::Door(DoorState::Closed), "Door");
+        registry.set_feature_tile(&Feature::Door(DoorState::Open), "OpenDoor");
+
+        assert_eq!(registry.feature_tile(&Feature::Door(DoorState::Closed)), Some("Door"));
+        assert_eq!(registry.feature_tile(&Feature::Door(DoorState::Open)), Some("OpenDoor"));
+        assert_eq!(registry.feature_tile(&Feature::UpStair), None);
+    }
+
+    #[test]
+    fn test_feature_names() {
+        let features = [Feature::Door(DoorState::Closed), Feature::Door(DoorState::Open),
+                        Feature::UpStair, Feature::DownStair];
+        for feature in features.iter() {
+            assert_eq!(Feature::from_name(feature.name()).as_ref(), Some(feature));
+        }
+        assert_eq!(Feature::from_name("trapdoor"), None);
+    }
+
+    #[test]
+    fn test_from_toml() {
+        let text = "
+[[terrain]]
+name = \"Wall\"
+glyph = \"#\"
+blocking = true
+group = \"stone\"
+
+[[terrain]]
+name = \"Floor\"
+glyph = \".\"
+group = \"floor\"
+
+[[groups]]
+name = \"floor\"
+connects_to = [\"stone\"]
+
+[features]
+door = \"Door\"
+";
+        let registry = TerrainRegistry::from_toml_str("test.toml", text).unwrap();
+        let wall = registry.find("Wall").unwrap();
+        let floor = registry.find("Floor").unwrap();
+        assert!(registry.get(wall).blocking);
+        assert!(registry.connections().connects(&floor, Some(&wall)));
+        assert_eq!(registry.feature_tile(&Feature::Door(DoorState::Closed)), Some("Door"));
+    }
+
+    #[test]
+    fn test_from_toml_errors() {
+        match TerrainRegistry::from_toml_str("test.toml", "[[groups]]\nname = \"floor\"\nconnects_to = [\"flor\"]\n") {
+            Err(TerrainError::UnknownGroup(_, ref group, ref other)) => {
+                assert_eq!(group, "floor");
+                assert_eq!(other, "flor");
+            },
+            _ => panic!("expected an unknown group error"),
+        }
+
+        match TerrainRegistry::from_toml_str("test.toml", "[[terrain]]\nname = \"Wall\"\nglyph = \"##\"\n") {
+            Err(TerrainError::Glyph(..)) => (),
+            _ => panic!("expected a glyph error"),
+        }
+
+        match TerrainRegistry::from_toml_str("test.toml", "[features]\ntrapdoor = \"Trapdoor\"\n") {
+            Err(TerrainError::UnknownFeature(..)) => (),
+            _ => panic!("expected an unknown feature error"),
+        }
+
+        match TerrainRegistry::from_toml_str("test.toml", "[[terrain]]\nglyph = \"#\"\n") {
+            Err(TerrainError::Config(..)) => (),
+            _ => panic!("expected a config error"),
+        }
+
+        match TerrainRegistry::from_toml("data/no-such-terrain.toml") {
+            Err(TerrainError::Io(..)) => (),
+            _ => panic!("expected an I/O error"),
+        }
+    }
+
+    #[test]
+    fn test_check_tiles() {
+        let mut registry = TerrainRegistry::new();
+        let mut wall = def("Wall", '#');
+        wall.tile = Some("StoneBrick".to_string());
+        registry.add(wall);
+        registry.add(def("Floor", '.'));
+        assert!(registry.check_tiles(tile_images()).is_ok());
+
+        let mut lava = def("Lava", '~');
+        lava.tile = Some("Lavva".to_string());
+        registry.add(lava);
+        match registry.check_tiles(tile_images()) {
+            Err(TerrainError::UnknownTile(ref terrain, ref tile)) => {
+                assert_eq!(terrain, "Lava");
+                assert_eq!(tile, "Lavva");
+            },
+            _ => panic!("expected an unknown tile error"),
+        }
+    }
+
+    #[test]
+    fn test_check_feature_tiles() {
+        let mut registry = TerrainRegistry::new();
+        registry.set_feature_tile(&Feature::Door(DoorState::Closed), "StoneBrick");
+        assert!(registry.check_feature_tiles(tile_images()).is_ok());
+
+        registry.set_feature_tile(&Feature::UpStair, "Ladder");
+        match registry.check_feature_tiles(tile_images()) {
+            Err(TerrainError::UnknownFeatureTile(ref feature, ref tile)) => {
+                assert_eq!(feature, "up_stair");
+                assert_eq!(tile, "Ladder");
+            },
+            _ => panic!("expected an unknown feature tile error"),
         }
     }
 }
@@ -0,0 +1,91 @@
+//! Fixtures shared by the tests of several modules.
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use std::sync::{Once, ONCE_INIT};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use atlas::{AtlasTile, TileImages, TileKind};
+use autotile::AutotileLayout;
+use terrain::{TerrainDef, TerrainRegistry};
+
+static LOAD_TILES: Once = ONCE_INIT;
+static TEMP_DIRS: AtomicUsize = ATOMIC_USIZE_INIT;
+static mut TILES: *const TileImages = 0 as *const TileImages;
+
+/// Gets the atlas of `data/tiles.toml`, loaded without a window. It's
+/// loaded once for every test, as tests loading it at the same time would
+/// race to pack it into `data/.packed`.
+pub fn tile_images() -> &'static TileImages {
+    unsafe {
+        LOAD_TILES.call_once(|| {
+            let tiles = match TileImages::from_config_headless("data/tiles.toml") {
+                Ok(t)  => t,
+                Err(e) => panic!("Failed to load tile atlas: {}", e),
+            };
+            TILES = Box::into_raw(Box::new(tiles));
+        });
+        &*TILES
+    }
+}
+
+/// Gets a registry with a blocking "Wall", drawn with the "StoneBrick" tile
+/// as `#`, and a "Floor", drawn with "Water" as `.`.
+pub fn terrains() -> Rc<TerrainRegistry> {
+    let mut registry = TerrainRegistry::new();
+    for &(name, glyph, tile, blocking) in &[("Wall", '#', "StoneBrick", true), ("Floor", '.', "Water", false)] {
+        registry.add(TerrainDef {
+            name: name.to_string(),
+            glyph: glyph,
+            tile: Some(tile.to_string()),
+            blocking: blocking,
+            group: None,
+        });
+    }
+    Rc::new(registry)
+}
+
+/// Gets an atlas tile at `offset` on its sheet, using the quadrant layout
+/// if it's an autotile.
+pub fn tile(offset: (u32, u32), is_autotile: bool, tile_kind: TileKind) -> AtlasTile {
+    AtlasTile {
+        offset: offset,
+        is_autotile: is_autotile,
+        autotile_layout: AutotileLayout::Quadrant,
+        tile_kind: tile_kind,
+    }
+}
+
+/// A folder of its own in the system temp folder, for a test to write files
+/// to. It's removed when dropped, even if the test fails.
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    /// Makes an empty folder named after `name`, the time and a count of
+    /// the folders made, so tests running at once never share one.
+    pub fn new(name: &str) -> TempDir {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.subsec_nanos()).unwrap_or(0);
+        let count = TEMP_DIRS.fetch_add(1, Ordering::SeqCst);
+        let path = env::temp_dir().join(format!("autotile-{}-{}-{}", name, nanos, count));
+
+        fs::create_dir_all(&path).unwrap();
+        TempDir {
+            path: path,
+        }
+    }
+
+    pub fn path(&self) -> String {
+        self.path.display().to_string()
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
@@ -2,6 +2,7 @@ use glium;
 use glium::glutin;
 use glium::backend::Facade;
 
+use atlas::PackingSettings;
 use render::{Renderable, Viewport};
 
 pub mod elements;
@@ -48,9 +49,9 @@ pub struct Ui {
 }
 
 impl Ui {
-    pub fn new<F: Facade>(display: &F) -> Self {
+    pub fn new<F: Facade>(display: &F, packing: PackingSettings) -> Self {
         Ui {
-            renderer: UiRenderer::new(display),
+            renderer: UiRenderer::new(display, packing),
             valid: false,
             layers: Vec::new(),
             main_layer: MainLayer::new(),
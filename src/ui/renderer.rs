@@ -6,7 +6,7 @@ use glium::backend::Facade;
 use glium::index::PrimitiveType;
 use glium::Rect;
 
-use atlas::AtlasRect;
+use atlas::{AtlasRect, PackingSettings};
 use atlas::font::FontTexture;
 use atlas::texture_atlas::*;
 use render::{self, Renderable, Viewport};
@@ -120,8 +120,8 @@ pub struct UiRenderer {
     color_stack: Vec<(u8, u8, u8, u8)>,
 }
 
-fn build_ui_atlas<F: Facade>(display: &F) -> TextureAtlas {
-    TextureAtlasBuilder::new()
+fn build_ui_atlas<F: Facade>(display: &F, packing: PackingSettings) -> TextureAtlas {
+    TextureAtlasBuilder::new(packing)
         .add_texture("win")
         .add_texture("textwin")
         .add_texture("bar")
@@ -129,7 +129,7 @@ fn build_ui_atlas<F: Facade>(display: &F) -> TextureAtlas {
 }
 
 impl UiRenderer {
-    pub fn new<F: Facade>(display: &F) -> Self {
+    pub fn new<F: Facade>(display: &F, packing: PackingSettings) -> Self {
         let font_size = 14;
 
         let font = FontTexture::new(display,
@@ -137,7 +137,7 @@ impl UiRenderer {
                                     font_size,
                                     FontTexture::ascii_character_list()).unwrap();
 
-        let atlas = build_ui_atlas(display);
+        let atlas = build_ui_atlas(display, packing);
         let program = render::load_program(display, "identity.vert", "identity.frag").unwrap();
         let font_program = render::load_program(display, "identity.vert", "font.frag").unwrap();
 
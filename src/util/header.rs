@@ -0,0 +1,48 @@
+//! The header at the start of binary files: a magic string naming the kind
+//! of file, followed by its format version as a little-endian `u32`.
+
+/// Makes the header for a file starting with `magic`, for the body to be
+/// appended to.
+pub fn write_header(magic: &[u8], version: u32) -> Vec<u8> {
+    let mut data = magic.to_vec();
+    data.extend_from_slice(&version_bytes(version));
+    data
+}
+
+/// Splits `data` into its version and the body after the header, or returns
+/// `None` if it doesn't start with `magic`.
+pub fn read_header<'a>(magic: &[u8], data: &'a [u8]) -> Option<(u32, &'a [u8])> {
+    let header_len = magic.len() + 4;
+    if data.len() < header_len || &data[..magic.len()] != magic {
+        return None;
+    }
+
+    let version = data[magic.len()..header_len].iter().rev().fold(0, |acc, &b| (acc << 8) | b as u32);
+    Some((version, &data[header_len..]))
+}
+
+fn version_bytes(version: u32) -> [u8; 4] {
+    [version as u8, (version >> 8) as u8, (version >> 16) as u8, (version >> 24) as u8]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header_roundtrip() {
+        let mut data = write_header(b"TEST", 0x01020304);
+        assert_eq!(data, b"TEST\x04\x03\x02\x01");
+
+        data.extend_from_slice(b"body");
+        assert_eq!(read_header(b"TEST", &data), Some((0x01020304, &b"body"[..])));
+    }
+
+    #[test]
+    fn test_rejects_other_magic() {
+        let data = write_header(b"TEST", 1);
+        assert_eq!(read_header(b"ELSE", &data), None);
+        assert_eq!(read_header(b"TEST", &data[..6]), None);
+        assert_eq!(read_header(b"TEST", &[]), None);
+    }
+}
@@ -1,5 +1,6 @@
 use std::time::Duration;
 
+pub mod header;
 pub mod toml;
 
 pub fn read_string(path: &str) -> String {
@@ -1,23 +1,42 @@
+use std::fmt;
 use std::fs::File;
-use std::io::Read;
+use std::io::{self, Read};
 use std::path::PathBuf;
 
 use serde::de::Deserialize;
 use toml::Value;
 
-pub fn toml_string_from_file(filename: &str) -> String {
+/// A problem with the contents of a TOML document.
+#[derive(Debug)]
+pub enum TomlError {
+    /// The text couldn't be parsed as TOML.
+    Parse(String),
+    /// A required key wasn't present in the table.
+    MissingKey(String),
+    /// The key was present, but its value had the wrong type.
+    InvalidValue(String),
+}
+
+impl fmt::Display for TomlError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TomlError::Parse(ref msg)       => write!(f, "invalid TOML: {}", msg),
+            TomlError::MissingKey(ref key)   => write!(f, "missing key \"{}\"", key),
+            TomlError::InvalidValue(ref key) => write!(f, "wrong type of value for key \"{}\"", key),
+        }
+    }
+}
+
+pub fn toml_string_from_file(filename: &str) -> io::Result<String> {
     let path = PathBuf::from(filename);
-    let mut file = match File::open(&path) {
-        Ok(f) => f,
-        Err(..) => panic!("Cannot open TOML file {}", path.display()),
-    };
+    let mut file = File::open(&path)?;
     let mut data = String::new();
-    file.read_to_string(&mut data).expect("Can't read TOML file!");
-    data
+    file.read_to_string(&mut data)?;
+    Ok(data)
 }
 
-pub fn toml_value_from_string(data: &str) -> Value {
-    data.parse::<Value>().expect("Invalid TOML!")
+pub fn toml_value_from_string(data: &str) -> Result<Value, TomlError> {
+    data.parse::<Value>().map_err(|e| TomlError::Parse(e.to_string()))
 }
 
 pub fn get_value_in_table<'a>(value: &'a Value, key: &str) -> Option<&'a Value> {
@@ -34,10 +53,18 @@ pub fn get_value_in_table<'a>(value: &'a Value, key: &str) -> Option<&'a Value>
     }
 }
 
-pub fn expect_value_in_table<'a, T: Deserialize>(value: &'a Value, key: &str) -> T {
+pub fn expect_value_in_table<'a, T: Deserialize>(value: &'a Value, key: &str) -> Result<T, TomlError> {
     match get_value_in_table(value,  key) {
-        Some(v) => v.clone().try_into::<T>().unwrap(),
-        None    => panic!("Expected value {} couldn't be parsed in table!", key),
+        Some(v) => v.clone().try_into::<T>().map_err(|_| TomlError::InvalidValue(key.to_string())),
+        None    => Err(TomlError::MissingKey(key.to_string())),
+    }
+}
+
+/// Like `expect_value_in_table`, but a missing key isn't an error.
+pub fn get_optional_value_in_table<T: Deserialize>(value: &Value, key: &str) -> Result<Option<T>, TomlError> {
+    match get_value_in_table(value, key) {
+        Some(v) => v.clone().try_into::<T>().map(Some).map_err(|_| TomlError::InvalidValue(key.to_string())),
+        None    => Ok(None),
     }
 }
 
@@ -61,7 +88,7 @@ mod tests {
         let val = toml_value_from_string("
 [table]
 thing=1
-dood=true");
+dood=true").unwrap();
         let res: Option<i32> = get_toml_value(&val, "table", "thing");
         assert!(res.is_some());
         let res: Option<bool> = get_toml_value(&val, "table", "none");
@@ -71,4 +98,20 @@ dood=true");
         let res: Option<bool> = get_toml_value(&val, "whee", "none");
         assert!(res.is_none());
     }
+
+    #[test]
+    fn test_expect_value() {
+        let val = toml_value_from_string("thing=1").unwrap();
+        let res: Result<i32, TomlError> = expect_value_in_table(&val, "thing");
+        assert_eq!(res.unwrap(), 1);
+
+        match expect_value_in_table::<i32>(&val, "none") {
+            Err(TomlError::MissingKey(ref key)) => assert_eq!(key, "none"),
+            _                                   => panic!("expected a missing key"),
+        }
+        match expect_value_in_table::<String>(&val, "thing") {
+            Err(TomlError::InvalidValue(ref key)) => assert_eq!(key, "thing"),
+            _                                     => panic!("expected an invalid value"),
+        }
+    }
 }